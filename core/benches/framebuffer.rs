@@ -0,0 +1,97 @@
+//! Pixel write throughput of `Canvas` as the number of threads grows,
+//! compared with a frame buffer behind a single `Mutex`, which every write
+//! has to lock. Run with `cargo bench --bench framebuffer [-- <max threads>]`.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rustracer_core::*;
+
+const WIDTH: usize = 1024;
+const HEIGHT: usize = 1024;
+const PASSES: usize = 8;
+
+struct LockedCanvas {
+    width: usize,
+    frame_buffer: Mutex<Vec<f32>>,
+}
+
+impl LockedCanvas {
+    fn new(width: usize, height: usize) -> LockedCanvas {
+        LockedCanvas {
+            width,
+            frame_buffer: Mutex::new(vec![0.; width * height * 4]),
+        }
+    }
+
+    fn set_color(&self, x: usize, y: usize, c: ColorRgbFloat) {
+        let start = (x + y * self.width) * 4;
+        let mut fb = self.frame_buffer.lock().unwrap();
+        fb[start..start + 4].copy_from_slice(&[c.r, c.g, c.b, 1.]);
+    }
+}
+
+/// Time taken by `n_threads` threads to set every pixel `PASSES` times,
+/// pulling tiles from a shared queue like the renderer does.
+fn fill<F>(n_threads: usize, set_color: F) -> Duration
+where
+    F: Fn(usize, usize, ColorRgbFloat) + Sync,
+{
+    let tiles = tiles(WIDTH, HEIGHT, DEFAULT_TILE_SIZE);
+    let next = AtomicUsize::new(0);
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for _ in 0..n_threads {
+            scope.spawn(|| {
+                while let Some(tile) = tiles.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    for pass in 0..PASSES {
+                        for (x, y) in tile.pixels() {
+                            let c = color(x as f32, y as f32, pass as f32);
+                            set_color(x, y, c);
+                        }
+                    }
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn mpixels_per_second(elapsed: Duration) -> f64 {
+    (WIDTH * HEIGHT * PASSES) as f64 / elapsed.as_secs_f64() / 1.0e6
+}
+
+fn main() {
+    let max_threads = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse::<usize>().ok())
+        .unwrap_or_else(num_cpus::get)
+        .max(1);
+    let mut n_threads = vec![];
+    let mut n = 1;
+    while n < max_threads {
+        n_threads.push(n);
+        n *= 2;
+    }
+    n_threads.push(max_threads);
+
+    println!(
+        "{:>8} {:>16} {:>16} {:>8}",
+        "threads", "mutex Mpx/s", "canvas Mpx/s", "ratio"
+    );
+    for n in n_threads {
+        let locked = LockedCanvas::new(WIDTH, HEIGHT);
+        let locked_time = fill(n, |x, y, c| locked.set_color(x, y, c));
+        let canvas = Canvas::new_hdr(WIDTH, HEIGHT);
+        let canvas_time = fill(n, |x, y, c| canvas.set_color(x, y, c));
+        println!(
+            "{:>8} {:>16.1} {:>16.1} {:>8.1}",
+            n,
+            mpixels_per_second(locked_time),
+            mpixels_per_second(canvas_time),
+            locked_time.as_secs_f64() / canvas_time.as_secs_f64()
+        );
+    }
+}
@@ -7,27 +7,30 @@ const F_PI_3: f32 = std::f32::consts::FRAC_PI_3;
 const F_PI_2: f32 = std::f32::consts::FRAC_PI_2;
 
 fn main() {
-    let mut floor_material = Material::default();
-
-    floor_material.color = Mapping::checkers(
-        &[BLACK, WHITE * 0.8],
-        rotation_y(F_PI_4) * scaling(0.5, 0.5, 0.5),
-    );
-    floor_material.specular = Mapping::from(0.7);
-    floor_material.reflective = Some(Mapping::from(0.2));
+    let floor_material = Material {
+        color: Mapping::checkers(
+            &[BLACK, WHITE * 0.8],
+            rotation_y(F_PI_4) * scaling(0.5, 0.5, 0.5),
+        ),
+        specular: Mapping::from(0.7),
+        reflective: Some(Mapping::from(0.2)),
+        ..Material::default()
+    };
 
     let floor = Box::new(Plane::new(Transform::identity(), floor_material.clone()));
 
-    let mut middle_material = Material::default();
-    middle_material.color = Mapping::stripes(
-        &[PURPLE * 0.7, PURPLE * 0.5],
-        rotation_z(F_PI_2) * scaling(0.2, 0.2, 0.2),
-    );
-    middle_material.specular = Mapping::from(1.);
-    middle_material.reflective = Some(Mapping::stripes(
-        &[0.03, 0.1],
-        rotation_z(F_PI_2) * scaling(0.2, 0.2, 0.2),
-    ));
+    let middle_material = Material {
+        color: Mapping::stripes(
+            &[PURPLE * 0.7, PURPLE * 0.5],
+            rotation_z(F_PI_2) * scaling(0.2, 0.2, 0.2),
+        ),
+        specular: Mapping::from(1.),
+        reflective: Some(Mapping::stripes(
+            &[0.03, 0.1],
+            rotation_z(F_PI_2) * scaling(0.2, 0.2, 0.2),
+        )),
+        ..Material::default()
+    };
 
     let sphere = Box::new(Sphere::new(
         translation(-0.5, 1., 0.5),
@@ -56,6 +59,6 @@ fn main() {
         vector(0., 1., 0.),
     ));
 
-    let canvas = camera.render(world);
-    canvas.save("./output/group.png");
+    let canvas = camera.render(world, &Whitted::default());
+    canvas.save("./output/group.png").unwrap();
 }
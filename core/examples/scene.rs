@@ -81,6 +81,7 @@ fn main() {
         transparency: Some(0.9.into()),
         refractive_index: 1.5,
         attenuation: Attenuation::Squared,
+        ..Material::default()
     };
 
     let left = Box::new(Sphere::new(
@@ -98,6 +99,7 @@ fn main() {
         transparency: Some(Mapping::checkers(&[0.01, 0.5], scaling(0.5, 0.5, 0.5))),
         refractive_index: 1.2,
         attenuation: Attenuation::Squared,
+        ..Material::default()
     };
 
     let cube = Box::new(Cube::new(
@@ -132,6 +134,6 @@ fn main() {
         vector(0., 1., 0.),
     ));
 
-    let canvas = camera.render(world);
-    canvas.save("./output/scene.png");
+    let canvas = camera.render(world, &Whitted::default());
+    canvas.save("./output/scene.png").unwrap();
 }
@@ -156,6 +156,6 @@ fn main() {
         point(0.0, 0.0, 0.0),
         vector(0., 1., 0.),
     ));
-    let canvas = camera.render(world);
-    canvas.save("./output/spheres.png");
+    let canvas = camera.render(world, &Whitted::default());
+    canvas.save("./output/spheres.png").unwrap();
 }
@@ -38,6 +38,7 @@ fn main() {
         transparency: None,
         refractive_index: 1.5,
         attenuation: Attenuation::Squared,
+        ..Material::default()
     });
 
     let group = Box::new(group);
@@ -54,6 +55,6 @@ fn main() {
         vector(0., 1., 0.),
     ));
 
-    let canvas = camera.render(world);
-    canvas.save("./output/teapot.png");
+    let canvas = camera.render(world, &Whitted::default());
+    canvas.save("./output/teapot.png").unwrap();
 }
@@ -39,6 +39,6 @@ fn main() {
         vector(0., 1., 0.),
     ));
 
-    let canvas = camera.render(world);
-    canvas.save("./output/triangle.png");
+    let canvas = camera.render(world, &Whitted::default());
+    canvas.save("./output/triangle.png").unwrap();
 }
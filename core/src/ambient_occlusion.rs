@@ -0,0 +1,94 @@
+use rand::{Rng, RngCore};
+
+use crate::*;
+
+/// Fraction of the hemisphere above a hit that isn't blocked by shapes closer
+/// than `max_distance`, estimated with `samples` cosine weighted rays. As an
+/// integrator it renders that fraction in grey levels, and `Whitted` can
+/// scale the ambient term of the materials with it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AmbientOcclusion {
+    pub samples: usize,
+    pub max_distance: f32,
+}
+
+impl AmbientOcclusion {
+    pub fn new(samples: usize, max_distance: f32) -> AmbientOcclusion {
+        AmbientOcclusion {
+            samples: samples.max(1),
+            max_distance,
+        }
+    }
+
+    /// Unoccluded fraction of the hemisphere around the normal of the hit, in
+    /// [0, 1].
+    pub fn visibility(&self, world: &World, hit: &Hit, rng: &mut dyn RngCore) -> f32 {
+        let time = hit.intersection.time;
+        let open = (0..self.samples)
+            .filter(|_| {
+                let direction = cosine_hemisphere(&hit.normalv, rng.gen(), rng.gen());
+                let direction = direction.into_inner();
+                let ray = Ray::new_with_time(hit.point + direction * EPS * 100., direction, time);
+                !world.is_blocked(&ray, self.max_distance)
+            })
+            .count();
+        open as f32 / self.samples as f32
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance(&self, world: &World, ray: &Ray, rng: &mut dyn RngCore) -> ColorRgbFloat {
+        match world.intersects(ray) {
+            Some(intersection) => {
+                let hit = intersection.prepare_hit(ray);
+                WHITE * self.visibility(world, &hit, rng)
+            }
+            None => BLACK,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A floor with a cube resting on it
+    fn floor_and_cube() -> World {
+        let floor = Plane::new(Transform::identity(), Material::default());
+        let cube = Cube::new(translation(0., 1., 0.), Material::default());
+        World::new(vec![Box::new(floor), Box::new(cube)], vec![])
+    }
+
+    #[test]
+    fn contact_areas_are_occluded() {
+        let world = floor_and_cube();
+        let ao = AmbientOcclusion::new(256, 1.);
+        let mut rng = rand::thread_rng();
+        let down = vector(0., -1., 0.);
+        let open = ao.radiance(&world, &Ray::new(point(5., 5., 0.), down), &mut rng);
+        assert_eq!(open, WHITE);
+        let contact = ao.radiance(&world, &Ray::new(point(1.05, 5., 0.), down), &mut rng);
+        assert!(contact.r > 0.3 && contact.r < 0.7);
+        // Beyond the maximum distance the cube doesn't count
+        let far = AmbientOcclusion::new(256, 0.01);
+        assert_eq!(
+            far.radiance(&world, &Ray::new(point(1.05, 5., 0.), down), &mut rng),
+            WHITE
+        );
+    }
+
+    #[test]
+    fn occlusion_darkens_ambient_term() {
+        let mut world = floor_and_cube();
+        world.lights = vec![Light::Point(PointLight::new(point(0., 10., -10.), BLACK))];
+        let ray = Ray::new(point(1.05, 5., 0.), vector(0., -1., 0.));
+        let mut rng = rand::thread_rng();
+        let flat = Whitted::default().radiance(&world, &ray, &mut rng);
+        assert_relative_eq!(flat, WHITE * 0.1);
+        let whitted = Whitted {
+            ambient_occlusion: Some(AmbientOcclusion::new(64, 1.)),
+            ..Whitted::default()
+        };
+        assert!(whitted.radiance(&world, &ray, &mut rng).r < flat.r * 0.8);
+    }
+}
@@ -0,0 +1,327 @@
+use std::path::Path;
+
+use image::{ImageFormat, ImageResult};
+
+use crate::*;
+
+/// Arbitrary output variable: per pixel surface data rendered along with the
+/// beauty image, for compositing and debugging.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Aov {
+    /// Distance to the first hit along the camera's view axis, or along the
+    /// ray for fisheye and equirectangular projections.
+    Depth,
+    /// World space normal, facing the camera.
+    Normal,
+    /// Material colour at the hit point, before lighting.
+    Albedo,
+    /// 1-based index of the world shape hit. Shapes inside groups get the
+    /// id of their top level group.
+    ObjectId,
+    /// `Material::id` of the shape hit.
+    MaterialId,
+    /// Surface coordinates of the hit, for shapes that provide them.
+    Uv,
+}
+
+pub const AOVS: [Aov; 6] = [
+    Aov::Depth,
+    Aov::Normal,
+    Aov::Albedo,
+    Aov::ObjectId,
+    Aov::MaterialId,
+    Aov::Uv,
+];
+
+impl Aov {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::Uv => "uv",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Aov> {
+        AOVS.iter().copied().find(|aov| aov.name() == name)
+    }
+
+    pub fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Normal => &["X", "Y", "Z"],
+            Aov::Albedo => &["R", "G", "B"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+            Aov::Uv => &["U", "V"],
+        }
+    }
+
+    /// Channel values for a pixel. Pixels without a hit are infinitely deep
+    /// and zero otherwise.
+    fn values(&self, surface: Option<&SurfaceSample>) -> [f32; 3] {
+        let surface = match surface {
+            Some(surface) => surface,
+            None if *self == Aov::Depth => return [f32::INFINITY, 0., 0.],
+            None => return [0.; 3],
+        };
+        match self {
+            Aov::Depth => [surface.depth, 0., 0.],
+            Aov::Normal => [surface.normal.x, surface.normal.y, surface.normal.z],
+            Aov::Albedo => [surface.albedo.r, surface.albedo.g, surface.albedo.b],
+            Aov::ObjectId => [surface.object_id as f32, 0., 0.],
+            Aov::MaterialId => [surface.material_id as f32, 0., 0.],
+            Aov::Uv => {
+                let (u, v) = surface.uv.unwrap_or_default();
+                [u, v, 0.]
+            }
+        }
+    }
+}
+
+/// Data of the first surface hit by a ray. See `World::surface_at`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SurfaceSample {
+    pub depth: f32,
+    pub normal: UnitVector,
+    pub albedo: ColorRgbFloat,
+    pub object_id: u32,
+    pub material_id: u32,
+    pub uv: Option<(f32, f32)>,
+}
+
+/// Values of one AOV for every pixel, row by row, with the channels of each
+/// pixel interleaved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AovBuffer {
+    pub aov: Aov,
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<f32>,
+}
+
+impl AovBuffer {
+    pub fn new(aov: Aov, width: usize, height: usize) -> AovBuffer {
+        let n_channels = aov.channels().len();
+        let background = aov.values(None);
+        let data = (0..width * height)
+            .flat_map(|_| background[..n_channels].to_vec())
+            .collect();
+        AovBuffer {
+            aov,
+            width,
+            height,
+            data,
+        }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, surface: Option<&SurfaceSample>) {
+        let n_channels = self.aov.channels().len();
+        let start = (x + y * self.width) * n_channels;
+        let values = self.aov.values(surface);
+        self.data[start..start + n_channels].copy_from_slice(&values[..n_channels]);
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> &[f32] {
+        let n_channels = self.aov.channels().len();
+        let start = (x + y * self.width) * n_channels;
+        &self.data[start..start + n_channels]
+    }
+
+    /// EXR channels of the buffer, in a layer named after the AOV.
+    pub fn exr_channels(&self) -> Vec<ExrChannel> {
+        let channels = self.aov.channels();
+        channels
+            .iter()
+            .enumerate()
+            .map(|(i, channel)| {
+                let samples = self
+                    .data
+                    .iter()
+                    .skip(i)
+                    .step_by(channels.len())
+                    .copied()
+                    .collect();
+                ExrChannel::new(&format!("{}.{}", self.aov.name(), channel), samples)
+            })
+            .collect()
+    }
+
+    /// Viewable 8 bit version of the buffer. Depth is scaled to the farthest
+    /// hit, normals from [-1, 1], and ids get arbitrary distinct colours.
+    pub fn to_canvas(&self) -> Canvas {
+        let canvas = Canvas::new(self.width, self.height);
+        let max_depth = self
+            .data
+            .iter()
+            .copied()
+            .filter(|d| d.is_finite())
+            .fold(0., f32::max);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let v = self.get(x, y);
+                let c = match self.aov {
+                    Aov::Depth if v[0].is_finite() && max_depth > 0. => {
+                        let d = v[0] / max_depth;
+                        color(d, d, d)
+                    }
+                    Aov::Depth => WHITE,
+                    Aov::Normal => color(v[0], v[1], v[2]) * 0.5 + color(0.5, 0.5, 0.5),
+                    Aov::Albedo => color(v[0], v[1], v[2]),
+                    Aov::ObjectId | Aov::MaterialId => id_color(v[0] as u32),
+                    Aov::Uv => color(v[0], v[1], 0.),
+                };
+                canvas.set(x, y, linear_to_byte(c));
+            }
+        }
+        canvas
+    }
+}
+
+impl Canvas {
+    /// Saves the canvas along with AOV buffers. OpenEXR files get the AOVs as
+    /// extra layers, in float precision so ids stay exact. Other formats get a
+    /// viewable image per AOV, named by `aov_filename`.
+    pub fn save_with_aovs(
+        &self,
+        filename: &str,
+        format: ImageFormat,
+        aovs: &[AovBuffer],
+    ) -> ImageResult<()> {
+        if format == ImageFormat::OpenExr {
+            let channels = aovs
+                .iter()
+                .flat_map(AovBuffer::exr_channels)
+                .collect::<Vec<_>>();
+            return self.save_exr(filename, ExrPrecision::Float, &channels);
+        }
+        self.save_with_format(filename, format)?;
+        for buffer in aovs {
+            let aov_filename = aov_filename(filename, buffer.aov);
+            buffer.to_canvas().save_with_format(&aov_filename, format)?;
+        }
+        Ok(())
+    }
+}
+
+/// `name.ext` becomes `name.<aov>.ext`.
+pub fn aov_filename(filename: &str, aov: Aov) -> String {
+    let path = Path::new(filename);
+    match path.extension() {
+        Some(ext) => path
+            .with_extension(format!("{}.{}", aov.name(), ext.to_string_lossy()))
+            .to_string_lossy()
+            .into_owned(),
+        None => format!("{}.{}", filename, aov.name()),
+    }
+}
+
+fn linear_to_byte(c: ColorRgbFloat) -> ColorRgbByte {
+    let byte = |x: f32| (x.clamp(0., 1.) * 255.).round() as u8;
+    ColorRgbByte {
+        r: byte(c.r),
+        g: byte(c.g),
+        b: byte(c.b),
+    }
+}
+
+fn id_color(id: u32) -> ColorRgbFloat {
+    if id == 0 {
+        return BLACK;
+    }
+    let hash = id.wrapping_mul(0x9E37_79B9);
+    let channel = |shift: u32| ((hash >> shift) & 0xFF) as f32 / 255.;
+    color(channel(24), channel(16), channel(8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> SurfaceSample {
+        SurfaceSample {
+            depth: 2.,
+            normal: UnitVector::new_normalize(vector(0., 0., -1.)),
+            albedo: color(0.5, 0.25, 1.),
+            object_id: 3,
+            material_id: 7,
+            uv: Some((0.25, 0.75)),
+        }
+    }
+
+    #[test]
+    fn aov_names() {
+        for aov in AOVS {
+            assert_eq!(Aov::from_name(aov.name()), Some(aov));
+        }
+        assert_eq!(Aov::from_name("beauty"), None);
+    }
+
+    #[test]
+    fn buffer_keeps_channels() {
+        let mut buffer = AovBuffer::new(Aov::Uv, 2, 2);
+        buffer.set(1, 0, Some(&sample()));
+        assert_eq!(buffer.get(1, 0), &[0.25, 0.75]);
+        assert_eq!(buffer.get(0, 1), &[0., 0.]);
+
+        let channels = buffer.exr_channels();
+        assert_eq!(channels[0].name, "uv.U");
+        assert_eq!(channels[1].samples, vec![0., 0.75, 0., 0.]);
+    }
+
+    #[test]
+    fn aov_filenames() {
+        assert_eq!(
+            aov_filename("out/render.png", Aov::Depth),
+            "out/render.depth.png"
+        );
+        assert_eq!(aov_filename("render", Aov::Uv), "render.uv");
+    }
+
+    #[test]
+    fn save_aovs_as_exr_layers() {
+        let filename = std::env::temp_dir().join("rustracer_aovs.exr");
+        let filename = filename.to_str().unwrap();
+        let mut ids = AovBuffer::new(Aov::ObjectId, 2, 1);
+        ids.set(1, 0, Some(&sample()));
+        let canvas = Canvas::new_hdr(2, 1);
+        canvas
+            .save_with_aovs(
+                filename,
+                ImageFormat::OpenExr,
+                &[ids, AovBuffer::new(Aov::Uv, 2, 1)],
+            )
+            .unwrap();
+        let image = ::exr::prelude::read_all_flat_layers_from_file(filename).unwrap();
+        std::fs::remove_file(filename).unwrap();
+        let channels = &image.layer_data[0].channel_data.list;
+        let names = channels
+            .iter()
+            .map(|c| c.name.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec!["A", "B", "G", "R", "object_id.id", "uv.U", "uv.V"]
+        );
+        assert_eq!(channels[4].sample_data.value_by_flat_index(1).to_f32(), 3.);
+    }
+
+    #[test]
+    fn depth_background_is_infinite() {
+        let mut buffer = AovBuffer::new(Aov::Depth, 2, 1);
+        buffer.set(0, 0, Some(&sample()));
+        assert_eq!(buffer.get(1, 0), &[f32::INFINITY]);
+        let canvas = buffer.to_canvas();
+        assert_eq!(canvas.get(0, 0), canvas.get(1, 0));
+        assert_eq!(
+            canvas.get(0, 0),
+            ColorRgbByte {
+                r: 255,
+                g: 255,
+                b: 255
+            }
+        );
+    }
+}
@@ -91,7 +91,7 @@ pub struct BoundedShape {
 
 impl BoundedShape {
     pub fn new(shape: Box<dyn Shape + Send>) -> BoundedShape {
-        let bounds = transform_bounds(&shape.get_bounds(), &shape.get_transform());
+        let bounds = shape.get_parent_bounds();
 
         BoundedShape {
             shape,
@@ -136,7 +136,10 @@ pub fn bvh_intersects<'a>(
     ray: &Ray,
 ) -> impl Iterator<Item=&'a BoundedShape> {
     let origin: Point3 = Point3::from_array(ray.origin.into());
-    let direction: Vector3 = Vector3::from_array(ray.direction.into());
+    // Adding zero turns -0 components into +0: the BVH ray would take the
+    // sign of -0 as positive and its inverse as -inf, and miss every box
+    let direction = ray.direction + Vector::zeros();
+    let direction: Vector3 = Vector3::from_array(direction.into());
     let bvh_ray = bvh::ray::Ray::new(origin, direction);
     bvh_iterator(bounded_shapes, &bvh.nodes, bvh_ray)
     // .map(move |index| &bounded_shapes[index])
@@ -219,6 +222,10 @@ impl<'a> Iterator for BvhIterator<'a> {
                     parent_index,
                     ..
                 } => {
+                    // Only a root leaf is ever reached again from below
+                    if let NodeTraversal::FromBottom(_) = traversal {
+                        return None;
+                    }
                     self.traversal = NodeTraversal::FromBottom(node_index);
                     self.node_index = *parent_index;
                     return Some(&self.bounded_shapes[*shape_index]);
@@ -1,17 +1,54 @@
-use std::sync::Arc;
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Barrier, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 use crate::*;
 
-#[derive(Debug, Copy, Clone)]
+// Refinement batches given to a pixel contrasting with a neighbour before its
+// standard error is trusted, since a few samples may all land on one side of
+// the edge.
+const MIN_EDGE_BATCHES: usize = 3;
+
+/// How directions in front of the camera are mapped to the image.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum Projection {
+    /// Pinhole camera, with the field of view spanning the larger image side.
+    #[default]
+    Perspective,
+    /// Parallel rays. `size` is the extent of the larger image side in world
+    /// units.
+    Orthographic { size: f32 },
+    /// Equidistant fisheye: the angle to the view axis grows linearly with the
+    /// distance to the image centre, reaching half the field of view at the
+    /// edges of the larger image side.
+    Fisheye,
+    /// 360° by 180° panorama, with longitude along x and latitude along y.
+    Equirectangular,
+}
+
+#[derive(Debug, Clone)]
 pub struct Camera {
     h_size: usize,
     v_size: usize,
+    field_of_view: f32,
+    projection: Projection,
     transform_inverse: Transform,
     half_width: f32,
     half_height: f32,
     pixel_size: f32,
-    max_reflects: u8,
+    n_threads: usize,
+    tile_size: usize,
+    sampling: PixelSampling,
+    tone_mapping: ToneMapping,
+    aovs: Vec<Aov>,
+    lens: Option<Lens>,
+    shutter: (f32, f32),
+    image_shift: (f32, f32),
 }
 
 impl Camera {
@@ -33,11 +70,20 @@ impl Camera {
         Camera {
             h_size,
             v_size,
+            field_of_view,
+            projection: Projection::default(),
             transform_inverse: Transform::identity(),
             half_width,
             half_height,
             pixel_size,
-            max_reflects: 5,
+            n_threads: num_cpus::get(),
+            tile_size: DEFAULT_TILE_SIZE,
+            sampling: PixelSampling::default(),
+            tone_mapping: ToneMapping::default(),
+            aovs: vec![],
+            lens: None,
+            shutter: (0., 0.),
+            image_shift: (0., 0.),
         }
     }
 
@@ -45,49 +91,472 @@ impl Camera {
         self.transform_inverse = transform.inverse();
     }
 
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    /// Number of worker threads used by `render`. Defaults to the number of CPUs.
+    pub fn set_threads(&mut self, n_threads: usize) {
+        self.n_threads = n_threads.max(1);
+    }
+
+    pub fn set_tile_size(&mut self, tile_size: usize) {
+        self.tile_size = tile_size.max(1);
+    }
+
+    pub fn set_sampling(&mut self, sampling: PixelSampling) {
+        self.sampling = sampling;
+    }
+
+    /// Tone mapping of the rendered canvases.
+    pub fn set_tone_mapping(&mut self, tone_mapping: ToneMapping) {
+        self.tone_mapping = tone_mapping;
+    }
+
+    /// AOVs rendered along with the image, from the centre of each pixel.
+    pub fn set_aovs(&mut self, aovs: &[Aov]) {
+        self.aovs = aovs.to_vec();
+    }
+
+    /// Lens used for depth of field. Without one the camera is a pinhole.
+    /// Fisheye and equirectangular projections ignore it.
+    pub fn set_lens(&mut self, lens: Option<Lens>) {
+        self.lens = lens;
+    }
+
+    /// Off-axis shift of the perspective image plane, in units of the plane at
+    /// distance 1. Positive values pan the view towards the left and top of
+    /// the image.
+    pub fn set_image_shift(&mut self, x: f32, y: f32) {
+        self.image_shift = (x, y);
+    }
+
+    /// Left and right eye cameras of a stereo rig centred on this camera. The
+    /// eyes are `interocular` apart with parallel axes, and their images are
+    /// shifted so objects at the `convergence` distance have no parallax.
+    pub fn stereo_pair(&self, interocular: f32, convergence: f32) -> (Camera, Camera) {
+        // Camera space x points to the left of the image
+        let eye = |side: f32| {
+            let offset = side * interocular / 2.;
+            let mut camera = self.clone();
+            camera.transform_inverse = self.transform_inverse * translation(offset, 0., 0.);
+            camera.image_shift.0 -= offset / convergence;
+            camera
+        };
+        (eye(1.), eye(-1.))
+    }
+
+    /// Interval the shutter stays open, over which the samples of each pixel
+    /// are spread in time to blur moving shapes.
+    pub fn set_shutter(&mut self, open: f32, close: f32) {
+        self.shutter = (open, close.max(open));
+    }
+
+    /// Instant at fraction `u` of the shutter interval.
+    fn shutter_time(&self, u: f32) -> f32 {
+        let (open, close) = self.shutter;
+        open + (close - open) * u
+    }
+
     pub fn ray_for_pixel(&self, x: usize, y: usize) -> Ray {
-        let x_offset = (x as f32 + 0.5) * self.pixel_size;
-        let y_offset = (y as f32 + 0.5) * self.pixel_size;
+        self.ray_for_position(x as f32 + 0.5, y as f32 + 0.5)
+    }
 
-        let world_x = self.half_width - x_offset;
-        let world_y = self.half_height - y_offset;
+    /// Ray through a point of the image plane, in (fractional) pixel units.
+    pub fn ray_for_position(&self, px: f32, py: f32) -> Ray {
+        self.ray_through_lens(px, py, (0.5, 0.5))
+    }
+
+    /// Ray through a point of the image plane, leaving from the point of the
+    /// lens given by `lens_uv` in the unit square. The lens centre is at
+    /// (0.5, 0.5), and the only point used by pinhole cameras. The ray is cast
+    /// halfway through the shutter interval.
+    pub fn ray_through_lens(&self, px: f32, py: f32, lens_uv: (f32, f32)) -> Ray {
+        let (origin, direction) = self.projected_ray(px, py);
+        let (origin, direction) = match (self.lens, self.projection) {
+            (Some(lens), Projection::Perspective | Projection::Orthographic { .. }) => {
+                let focus = origin + direction * (lens.focal_distance / -direction.z);
+                let (lens_x, lens_y) = lens.sample(lens_uv.0, lens_uv.1);
+                let origin = origin + vector(lens_x, lens_y, 0.);
+                (origin, focus - origin)
+            }
+            _ => (origin, direction),
+        };
 
         let transform_inv = &self.transform_inverse;
-        let pixel = transform_inv * point(world_x, world_y, -1.);
-        let origin = transform_inv * point(0., 0., 0.);
-        let direction = normalize(&(pixel - origin));
-        Ray::new(origin, direction.into_inner())
-    }
-
-    pub fn render(self, world: World) -> Canvas {
-        let canvas = Canvas::new(self.h_size, self.v_size);
-        let canvas = Arc::new(canvas);
-        let world = Arc::new(world);
-        let camera = Arc::new(self);
-
-        let mut handles = vec![];
-        let n_threads = num_cpus::get();
-        for i in 0..n_threads {
-            let shared_canvas = Arc::clone(&canvas);
-            let world = Arc::clone(&world);
-            let camera = Arc::clone(&camera);
-            let handle = thread::spawn(move || {
-                for y in (i..=camera.v_size - n_threads + i).step_by(n_threads) {
-                    for x in 0..camera.h_size {
-                        let ray = camera.ray_for_pixel(x, y);
-                        let color = world.color_at(&ray, camera.max_reflects).into();
-                        shared_canvas.set(x, y, color);
-                    }
+        let origin = transform_inv * origin;
+        let direction = normalize(&(transform_inv * direction));
+        Ray::new_with_time(origin, direction.into_inner(), self.shutter_time(0.5))
+    }
+
+    /// Origin and direction, in camera space, of the pinhole ray through a
+    /// point of the image. The camera looks towards -z.
+    fn projected_ray(&self, px: f32, py: f32) -> (Point, Vector) {
+        let origin = point(0., 0., 0.);
+        let (h_size, v_size) = (self.h_size as f32, self.v_size as f32);
+        match self.projection {
+            Projection::Perspective => {
+                // Image plane at z = -1
+                let world_x = self.half_width - px * self.pixel_size + self.image_shift.0;
+                let world_y = self.half_height - py * self.pixel_size + self.image_shift.1;
+                (origin, vector(world_x, world_y, -1.))
+            }
+            Projection::Orthographic { size } => {
+                let scale = size / h_size.max(v_size);
+                let x = (h_size / 2. - px) * scale;
+                let y = (v_size / 2. - py) * scale;
+                (point(x, y, 0.), vector(0., 0., -1.))
+            }
+            Projection::Fisheye => {
+                let (x, y) = (h_size / 2. - px, v_size / 2. - py);
+                let r = x.hypot(y);
+                if r == 0. {
+                    return (origin, vector(0., 0., -1.));
+                }
+                let theta = r * self.field_of_view / h_size.max(v_size);
+                let sin_theta = theta.sin();
+                (
+                    origin,
+                    vector(sin_theta * x / r, sin_theta * y / r, -theta.cos()),
+                )
+            }
+            Projection::Equirectangular => {
+                let longitude = PI * (1. - 2. * px / h_size);
+                let latitude = PI * (0.5 - py / v_size);
+                let direction = vector(
+                    longitude.sin() * latitude.cos(),
+                    latitude.sin(),
+                    -longitude.cos() * latitude.cos(),
+                );
+                (origin, direction)
+            }
+        }
+    }
+
+    /// Adds the `batch`-th batch of samples of pixel `(x, y)` to `estimate`.
+    pub fn sample_pixel<R: Rng>(
+        &self,
+        world: &World,
+        integrator: &dyn Integrator,
+        (x, y): (usize, usize),
+        batch: usize,
+        estimate: &mut PixelEstimate,
+        rng: &mut R,
+    ) {
+        for (dx, dy, weight) in self.sampling.pixel_samples(x, y, batch, rng) {
+            if weight == 0. {
+                continue;
+            }
+            let (px, py) = (x as f32 + 0.5 + dx, y as f32 + 0.5 + dy);
+            let mut ray = match self.lens {
+                Some(_) => self.ray_through_lens(px, py, (rng.gen(), rng.gen())),
+                None => self.ray_for_position(px, py),
+            };
+            if self.shutter.0 < self.shutter.1 {
+                ray.time = self.shutter_time(rng.gen());
+            }
+            estimate.add(integrator.radiance(world, &ray, rng), weight);
+        }
+    }
+
+    /// Filtered average of the samples taken for pixel `(x, y)`.
+    pub fn color_for_pixel<R: Rng>(
+        &self,
+        world: &World,
+        integrator: &dyn Integrator,
+        x: usize,
+        y: usize,
+        rng: &mut R,
+    ) -> ColorRgbFloat {
+        let mut estimate = PixelEstimate::default();
+        self.sample_pixel(world, integrator, (x, y), 0, &mut estimate, rng);
+        estimate.color()
+    }
+
+    /// Surface data at the centre of pixel `(x, y)`, with camera space depth
+    /// for the planar projections. Fisheye and equirectangular rays span
+    /// more than a hemisphere, so their depth is the distance along the ray.
+    pub fn surface_for_pixel(&self, world: &World, x: usize, y: usize) -> Option<SurfaceSample> {
+        let ray = self.ray_for_pixel(x, y);
+        let surface = world.surface_at(&ray)?;
+        match self.projection {
+            Projection::Perspective | Projection::Orthographic { .. } => {
+                let forward = normalize(&(self.transform_inverse * vector(0., 0., -1.)));
+                Some(SurfaceSample {
+                    depth: surface.depth * dot(&ray.direction, &forward),
+                    ..surface
+                })
+            }
+            Projection::Fisheye | Projection::Equirectangular => Some(surface),
+        }
+    }
+
+    /// Renders the rows of `tile` until `must_stop` returns true, returning the
+    /// estimates of the rendered pixels row by row. With adaptive sampling, a
+    /// fully rendered tile publishes the luminance of its first pass in
+    /// `first_pass`.
+    fn render_tile<R: Rng>(
+        &self,
+        world: &World,
+        integrator: &dyn Integrator,
+        tile: &Tile,
+        first_pass: &[AtomicU32],
+        must_stop: &dyn Fn() -> bool,
+        rng: &mut R,
+    ) -> Vec<PixelEstimate> {
+        let mut estimates = Vec::with_capacity(tile.len());
+        for y in tile.y..tile.y + tile.height {
+            if must_stop() {
+                break;
+            }
+            for x in tile.x..tile.x + tile.width {
+                let mut estimate = PixelEstimate::default();
+                self.sample_pixel(world, integrator, (x, y), 0, &mut estimate, rng);
+                estimates.push(estimate);
+            }
+        }
+
+        if self.sampling.adaptive.is_some() && estimates.len() == tile.len() {
+            for ((x, y), estimate) in tile.pixels().zip(&estimates) {
+                let luminance = estimate.luminance().to_bits();
+                first_pass[x + y * self.h_size].store(luminance, Ordering::Relaxed);
+            }
+        }
+        estimates
+    }
+
+    /// Adds batches of samples to the pixels of the tile that differ from one
+    /// of their neighbours, or are too noisy, until they converge or reach
+    /// the maximum number of samples. Pixels are compared through their first
+    /// pass, so every tile must have published it before any is refined.
+    /// Pixels of tiles left unrendered have no first pass and aren't compared.
+    #[allow(clippy::too_many_arguments)]
+    fn refine_tile<R: Rng>(
+        &self,
+        world: &World,
+        integrator: &dyn Integrator,
+        tile: &Tile,
+        estimates: &mut [PixelEstimate],
+        first_pass: &[AtomicU32],
+        adaptive: AdaptiveSampling,
+        must_stop: &dyn Fn() -> bool,
+        rng: &mut R,
+    ) {
+        let width = tile.width;
+        let luminance_at = |x: usize, y: usize| {
+            f32::from_bits(first_pass[x + y * self.h_size].load(Ordering::Relaxed))
+        };
+
+        // Pixels to refine, and whether they contrast with a neighbour
+        let mut flagged = vec![];
+        for (i, (x, y)) in tile.pixels().enumerate() {
+            let luminance = luminance_at(x, y);
+            let neighbours = [
+                (x.wrapping_sub(1), y),
+                (x + 1, y),
+                (x, y.wrapping_sub(1)),
+                (x, y + 1),
+            ];
+            let edge = neighbours
+                .into_iter()
+                .filter(|&(x, y)| x < self.h_size && y < self.v_size)
+                .any(|(x, y)| (luminance_at(x, y) - luminance).abs() > adaptive.threshold);
+            if edge || estimates[i].std_error() > adaptive.threshold {
+                flagged.push((i, edge));
+            }
+        }
+
+        for (i, edge) in flagged {
+            if must_stop() {
+                return;
+            }
+            let pixel = (tile.x + i % width, tile.y + i / width);
+            let estimate = &mut estimates[i];
+            let mut batch = 1;
+            while estimate.samples < adaptive.max_samples {
+                self.sample_pixel(world, integrator, pixel, batch, estimate, rng);
+                batch += 1;
+                let converged = estimate.std_error() <= adaptive.threshold;
+                if converged && (!edge || batch > MIN_EDGE_BATCHES) {
+                    break;
                 }
-            });
-            handles.push(handle);
+            }
         }
+    }
+
+    pub fn render(self, world: World, integrator: &dyn Integrator) -> Canvas {
+        self.render_with_progress(&world, integrator, |_| {})
+    }
+
+    /// Renders the world tile by tile, calling `on_progress` from the worker
+    /// threads every time a tile is finished.
+    pub fn render_with_progress<F>(
+        &self,
+        world: &World,
+        integrator: &dyn Integrator,
+        on_progress: F,
+    ) -> Canvas
+    where
+        F: Fn(&RenderProgress) + Sync,
+    {
+        self.render_partial(world, integrator, &CancelToken::new(), None, on_progress)
+            .canvas
+    }
+
+    /// Renders until finished, cancelled through `token` or out of `budget`.
+    /// Cancellation is checked before each row of a tile, so the pixels
+    /// already rendered are kept and flagged in the returned mask.
+    pub fn render_partial<F>(
+        &self,
+        world: &World,
+        integrator: &dyn Integrator,
+        token: &CancelToken,
+        budget: Option<Duration>,
+        on_progress: F,
+    ) -> PartialRender
+    where
+        F: Fn(&RenderProgress) + Sync,
+    {
+        let deadline = budget.map(|budget| Instant::now() + budget);
+        let mut canvas = Canvas::new_hdr(self.h_size, self.v_size);
+        canvas.set_tone_mapping(self.tone_mapping);
+        let queue = TileQueue::new(tiles(self.h_size, self.v_size, self.tile_size));
+        let done_tiles = Mutex::new(vec![]);
+        // First-pass luminance of every pixel for adaptive sampling, NaN until
+        // known
+        let first_pass = match self.sampling.adaptive {
+            Some(_) => (0..self.h_size * self.v_size)
+                .map(|_| AtomicU32::new(f32::NAN.to_bits()))
+                .collect(),
+            None => vec![],
+        };
+
+        // The deadline is checked on its own, leaving the caller's token as it
+        // was so that it can be shared with other renders
+        let must_stop =
+            || token.is_cancelled() || deadline.is_some_and(|deadline| Instant::now() >= deadline);
+
+        // Fully rendered tiles, refined once every tile has published its first
+        // pass
+        let pending = Mutex::new(vec![]);
+        let barrier = Barrier::new(self.n_threads);
+        let finish_tile = |tile: Tile, estimates: Vec<PixelEstimate>| {
+            let rendered = Tile {
+                height: estimates.len() / tile.width,
+                ..tile
+            };
+            for ((x, y), estimate) in rendered.pixels().zip(&estimates) {
+                canvas.set_color(x, y, estimate.color());
+            }
+            let samples = estimates.iter().map(|e| e.samples).collect::<Vec<_>>();
+            let rays = samples.iter().sum::<usize>() as u64;
+            let surfaces = if self.aovs.is_empty() {
+                vec![]
+            } else {
+                rendered
+                    .pixels()
+                    .map(|(x, y)| self.surface_for_pixel(world, x, y))
+                    .collect()
+            };
+            done_tiles
+                .lock()
+                .unwrap()
+                .push((rendered, samples, surfaces));
+
+            if rendered.height == tile.height {
+                on_progress(&queue.complete(tile, rays));
+            }
+        };
+
+        thread::scope(|scope| {
+            for _ in 0..self.n_threads {
+                scope.spawn(|| {
+                    let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
+                    while let Some(tile) = queue.next_tile() {
+                        let estimates = self.render_tile(
+                            world,
+                            integrator,
+                            &tile,
+                            &first_pass,
+                            &must_stop,
+                            &mut rng,
+                        );
+                        let complete = estimates.len() == tile.len();
+                        if complete && self.sampling.adaptive.is_some() {
+                            pending.lock().unwrap().push((tile, estimates));
+                            continue;
+                        }
+                        finish_tile(tile, estimates);
+                        if !complete {
+                            break;
+                        }
+                    }
 
-        for handle in handles {
-            handle.join().unwrap();
+                    let Some(adaptive) = self.sampling.adaptive else {
+                        return;
+                    };
+                    barrier.wait();
+                    loop {
+                        let next = pending.lock().unwrap().pop();
+                        let Some((tile, mut estimates)) = next else {
+                            break;
+                        };
+                        self.refine_tile(
+                            world,
+                            integrator,
+                            &tile,
+                            &mut estimates,
+                            &first_pass,
+                            adaptive,
+                            &must_stop,
+                            &mut rng,
+                        );
+                        finish_tile(tile, estimates);
+                    }
+                });
+            }
+        });
+
+        let mut mask = RenderMask::new(self.h_size, self.v_size);
+        let mut sample_counts = vec![0; self.h_size * self.v_size];
+        let mut aovs = self
+            .aovs
+            .iter()
+            .map(|&aov| AovBuffer::new(aov, self.h_size, self.v_size))
+            .collect::<Vec<_>>();
+        for (tile, samples, surfaces) in done_tiles.into_inner().unwrap() {
+            mask.set_tile(&tile);
+            for ((x, y), n) in tile.pixels().zip(samples) {
+                sample_counts[x + y * self.h_size] = n;
+            }
+            for ((x, y), surface) in tile.pixels().zip(surfaces) {
+                for buffer in &mut aovs {
+                    buffer.set(x, y, surface.as_ref());
+                }
+            }
         }
+        PartialRender {
+            canvas,
+            mask,
+            sample_counts,
+            aovs,
+        }
+    }
 
-        Arc::try_unwrap(canvas).unwrap()
+    /// Starts rendering on a background thread. The returned handle can
+    /// cancel the render and collect the (possibly partial) result.
+    pub fn spawn_render(
+        self,
+        world: World,
+        integrator: Box<dyn Integrator>,
+        budget: Option<Duration>,
+    ) -> RenderHandle {
+        let token = CancelToken::new();
+        let thread_token = token.clone();
+        let thread = thread::spawn(move || {
+            self.render_partial(&world, integrator.as_ref(), &thread_token, budget, |_| {})
+        });
+        RenderHandle::new(token, thread)
     }
 }
 
@@ -164,6 +633,235 @@ mod tests {
         );
     }
 
+    #[test]
+    fn construct_ray_for_position() {
+        let c = Camera::new(201, 101, std::f32::consts::FRAC_PI_2);
+        let r: Ray = c.ray_for_position(0.5, 0.5);
+        assert_relative_eq!(r.direction, c.ray_for_pixel(0, 0).direction);
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let mut c = Camera::new(200, 100, std::f32::consts::FRAC_PI_2);
+        c.set_projection(Projection::Orthographic { size: 10. });
+        let r = c.ray_for_position(0., 100.);
+        assert_relative_eq!(r.origin, point(5., -2.5, 0.));
+        assert_relative_eq!(r.direction, vector(0., 0., -1.));
+        assert_relative_eq!(c.ray_for_position(100., 50.).origin, point(0., 0., 0.));
+    }
+
+    #[test]
+    fn fisheye_angle_grows_linearly() {
+        let mut c = Camera::new(200, 100, PI);
+        c.set_projection(Projection::Fisheye);
+        assert_relative_eq!(c.ray_for_position(100., 50.).direction, vector(0., 0., -1.));
+        // Half the field of view at the edges of the larger side
+        assert_relative_eq!(c.ray_for_position(0., 50.).direction, vector(1., 0., 0.));
+        let r = c.ray_for_position(100., 0.);
+        let angle = PI / 4.;
+        assert_relative_eq!(r.direction, vector(0., angle.sin(), -angle.cos()));
+    }
+
+    #[test]
+    fn equirectangular_covers_the_sphere() {
+        let mut c = Camera::new(200, 100, std::f32::consts::FRAC_PI_2);
+        c.set_projection(Projection::Equirectangular);
+        let direction = |px, py| c.ray_for_position(px, py).direction;
+        assert_relative_eq!(direction(100., 50.), vector(0., 0., -1.));
+        assert_relative_eq!(direction(50., 50.), vector(1., 0., 0.), epsilon = 1e-6);
+        assert_relative_eq!(direction(0., 50.), vector(0., 0., 1.), epsilon = 1e-6);
+        assert_relative_eq!(direction(100., 0.), vector(0., 1., 0.), epsilon = 1e-6);
+    }
+
+    #[test]
+    fn equirectangular_depth_is_along_the_ray() {
+        let mut c = Camera::new(8, 4, std::f32::consts::FRAC_PI_2);
+        c.set_projection(Projection::Equirectangular);
+        // From the centre of the inner sphere, behind and beside the camera
+        let world = World::default();
+        for x in [0, 2, 4] {
+            let surface = c.surface_for_pixel(&world, x, 2).unwrap();
+            assert_relative_eq!(surface.depth, 0.5, epsilon = 1e-4);
+        }
+    }
+
+    #[test]
+    fn lens_rays_converge_on_focal_plane() {
+        let mut c = Camera::new(201, 101, std::f32::consts::FRAC_PI_2);
+        let pinhole = c.ray_for_position(10.5, 20.5);
+        let focus = pinhole.position(4. / -pinhole.direction.z);
+        c.set_lens(Some(Lens::new(0.5, 4.)));
+        assert_relative_eq!(c.ray_for_position(10.5, 20.5).origin, point(0., 0., 0.));
+        for lens_uv in [(0., 0.), (1., 0.3), (0.2, 0.9)] {
+            let r = c.ray_through_lens(10.5, 20.5, lens_uv);
+            assert_relative_eq!(r.origin.z, 0.);
+            assert!(r.origin.x.hypot(r.origin.y) > 0.1);
+            let t = (focus.z - r.origin.z) / r.direction.z;
+            assert_relative_eq!(r.position(t), focus, epsilon = 1e-4);
+        }
+    }
+
+    #[test]
+    fn stereo_eyes_converge() {
+        let mut c = Camera::new(101, 101, std::f32::consts::FRAC_PI_2);
+        c.set_transform(view_transform(
+            point(0., 0., -5.),
+            point(0., 0., 0.),
+            vector(0., 1., 0.),
+        ));
+        let (left, right) = c.stereo_pair(0.5, 5.);
+        let (l, r) = (left.ray_for_pixel(50, 50), right.ray_for_pixel(50, 50));
+        assert_relative_eq!(l.origin, point(-0.25, 0., -5.), epsilon = 1e-5);
+        assert_relative_eq!(r.origin, point(0.25, 0., -5.), epsilon = 1e-5);
+        // Both centre rays meet on the convergence plane
+        assert_relative_eq!(
+            l.position(5. / l.direction.z),
+            point(0., 0., 0.),
+            epsilon = 1e-5
+        );
+        assert_relative_eq!(
+            r.position(5. / r.direction.z),
+            point(0., 0., 0.),
+            epsilon = 1e-5
+        );
+    }
+
+    #[test]
+    fn rays_spread_over_shutter() {
+        let mut c = Camera::new(11, 11, std::f32::consts::FRAC_PI_2);
+        assert_eq!(c.ray_for_pixel(5, 5).time, 0.);
+        c.set_shutter(0.5, 1.5);
+        assert_eq!(c.ray_for_pixel(5, 5).time, 1.);
+        assert_relative_eq!(c.shutter_time(0.), 0.5);
+        assert_relative_eq!(c.shutter_time(1.), 1.5);
+    }
+
+    #[test]
+    fn supersampled_flat_color() {
+        let world = World::default();
+        let mut camera = Camera::new(11, 11, std::f32::consts::FRAC_PI_2);
+        camera.set_transform(view_transform(
+            point(0., 0., -5.),
+            point(0., 0., 0.),
+            vector(0., 1., 0.),
+        ));
+        camera.set_sampling(PixelSampling::new(SamplingStrategy::Sobol, Filter::Box, 16));
+        let mut rng = rand::thread_rng();
+        let whitted = Whitted::default();
+        // Background pixel: every sample misses
+        assert_relative_eq!(
+            camera.color_for_pixel(&world, &whitted, 0, 0, &mut rng),
+            BLACK
+        );
+        let centre = camera.color_for_pixel(&world, &whitted, 5, 5, &mut rng);
+        assert_relative_eq!(centre, color(0.38066, 0.47583, 0.2855), epsilon = 0.02);
+    }
+
+    #[test]
+    fn adaptive_sampling_refines_edges_only() {
+        let mut camera = Camera::new(11, 11, std::f32::consts::FRAC_PI_2);
+        camera.set_transform(view_transform(
+            point(0., 0., -5.),
+            point(0., 0., 0.),
+            vector(0., 1., 0.),
+        ));
+        camera.set_sampling(PixelSampling {
+            adaptive: Some(AdaptiveSampling {
+                max_samples: 16,
+                threshold: 0.01,
+            }),
+            ..PixelSampling::new(SamplingStrategy::Stratified, Filter::Box, 4)
+        });
+        let (world, whitted) = (World::default(), Whitted::default());
+        let partial = camera.render_partial(&world, &whitted, &CancelToken::new(), None, |_| {});
+        let counts = &partial.sample_counts;
+        // Flat background in the corner, silhouette of the sphere in the middle row
+        assert_eq!(counts[0], 4);
+        assert!(counts.contains(&16));
+    }
+
+    #[test]
+    fn adaptive_sampling_refines_edges_across_tiles() {
+        let mut camera = Camera::new(11, 11, std::f32::consts::FRAC_PI_2);
+        camera.set_transform(view_transform(
+            point(0., 0., -5.),
+            point(0., 0., 0.),
+            vector(0., 1., 0.),
+        ));
+        camera.set_sampling(PixelSampling {
+            adaptive: Some(AdaptiveSampling {
+                max_samples: 16,
+                threshold: 0.01,
+            }),
+            ..PixelSampling::new(SamplingStrategy::Regular, Filter::Box, 1)
+        });
+        // Every edge falls between two tiles
+        camera.set_tile_size(1);
+        let (world, whitted) = (World::default(), Whitted::default());
+        let partial = camera.render_partial(&world, &whitted, &CancelToken::new(), None, |_| {});
+        let counts = &partial.sample_counts;
+        // A single sample has no standard error, so only the contrast with
+        // neighbouring tiles can flag the silhouette
+        assert_eq!(counts[0], 1);
+        assert!(counts.iter().any(|&n| n > MIN_EDGE_BATCHES));
+    }
+
+    #[test]
+    fn adaptive_sampling_ignores_thread_timing() {
+        let mut camera = Camera::new(11, 11, std::f32::consts::FRAC_PI_2);
+        camera.set_transform(view_transform(
+            point(0., 0., -5.),
+            point(0., 0., 0.),
+            vector(0., 1., 0.),
+        ));
+        camera.set_sampling(PixelSampling {
+            adaptive: Some(AdaptiveSampling {
+                max_samples: 16,
+                threshold: 0.01,
+            }),
+            ..PixelSampling::new(SamplingStrategy::Regular, Filter::Box, 1)
+        });
+        camera.set_tile_size(1);
+        let (world, whitted) = (World::default(), Whitted::default());
+        let refined = |n_threads| {
+            let mut camera = camera.clone();
+            camera.set_threads(n_threads);
+            let partial =
+                camera.render_partial(&world, &whitted, &CancelToken::new(), None, |_| {});
+            partial
+                .sample_counts
+                .iter()
+                .map(|&n| n > 1)
+                .collect::<Vec<_>>()
+        };
+        let expected = refined(1);
+        for _ in 0..4 {
+            assert_eq!(refined(4), expected);
+        }
+    }
+
+    #[test]
+    fn render_aovs() {
+        let mut camera = Camera::new(11, 11, std::f32::consts::FRAC_PI_2);
+        camera.set_transform(view_transform(
+            point(0., 0., -5.),
+            point(0., 0., 0.),
+            vector(0., 1., 0.),
+        ));
+        camera.set_aovs(&[Aov::Depth, Aov::Normal, Aov::Albedo, Aov::ObjectId]);
+        let (world, whitted) = (World::default(), Whitted::default());
+        let partial = camera.render_partial(&world, &whitted, &CancelToken::new(), None, |_| {});
+        let [depth, normal, albedo, id] = &partial.aovs[..] else {
+            panic!("expected 4 AOV buffers");
+        };
+        assert_relative_eq!(depth.get(5, 5)[0], 4., epsilon = 1e-4);
+        assert_eq!(depth.get(0, 0), &[f32::INFINITY]);
+        assert_relative_eq!(normal.get(5, 5)[2], -1., epsilon = 1e-4);
+        assert_eq!(albedo.get(5, 5), &[0.8, 1., 0.6]);
+        assert_eq!(id.get(5, 5), &[1.]);
+        assert_eq!(id.get(0, 0), &[0.]);
+    }
+
     #[test]
     fn render_default_world() {
         let world = World::default();
@@ -172,7 +870,68 @@ mod tests {
         let to = point(0., 0., 0.);
         let up = vector(0., 1., 0.);
         camera.set_transform(view_transform(from, to, up));
-        let canvas = camera.render(world);
+        let canvas = camera.render(world, &Whitted::default());
         assert_eq!(canvas.get(5, 5), color(0.38066, 0.47583, 0.2855).into());
     }
+
+    #[test]
+    fn render_reports_progress() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let world = World::default();
+        let mut camera = Camera::new(11, 11, std::f32::consts::FRAC_PI_2);
+        camera.set_transform(view_transform(
+            point(0., 0., -5.),
+            point(0., 0., 0.),
+            vector(0., 1., 0.),
+        ));
+        camera.set_tile_size(4);
+        camera.set_threads(2);
+        let reported = AtomicUsize::new(0);
+        let canvas = camera.render_with_progress(&world, &Whitted::default(), |progress| {
+            assert_eq!(progress.tiles_total, 9);
+            reported.fetch_add(1, Ordering::Relaxed);
+        });
+        assert_eq!(reported.load(Ordering::Relaxed), 9);
+        assert_eq!(canvas.get(5, 5), color(0.38066, 0.47583, 0.2855).into());
+    }
+
+    #[test]
+    fn cancelled_render_keeps_nothing() {
+        let camera = Camera::new(11, 11, std::f32::consts::FRAC_PI_2);
+        let token = CancelToken::new();
+        token.cancel();
+        let (world, whitted) = (World::default(), Whitted::default());
+        let partial = camera.render_partial(&world, &whitted, &token, None, |_| {});
+        assert_eq!(partial.mask.count_done(), 0);
+        assert!(!partial.is_complete());
+    }
+
+    #[test]
+    fn render_out_of_budget() {
+        let camera = Camera::new(11, 11, std::f32::consts::FRAC_PI_2);
+        let token = CancelToken::new();
+        let budget = Some(Duration::ZERO);
+        let (world, whitted) = (World::default(), Whitted::default());
+        let partial = camera.render_partial(&world, &whitted, &token, budget, |_| {});
+        assert!(!partial.is_complete());
+        assert!(!token.is_cancelled());
+    }
+
+    #[test]
+    fn spawned_render_completes() {
+        let mut camera = Camera::new(11, 11, std::f32::consts::FRAC_PI_2);
+        camera.set_transform(view_transform(
+            point(0., 0., -5.),
+            point(0., 0., 0.),
+            vector(0., 1., 0.),
+        ));
+        let handle = camera.spawn_render(World::default(), Box::new(Whitted::default()), None);
+        let partial = handle.join();
+        assert!(partial.is_complete());
+        assert_eq!(
+            partial.canvas.get(5, 5),
+            color(0.38066, 0.47583, 0.2855).into()
+        );
+    }
 }
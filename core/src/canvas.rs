@@ -1,16 +1,31 @@
-use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
 
-use image::ImageBuffer;
+use image::{ImageBuffer, ImageFormat, ImageResult};
 
 use crate::*;
 
 const N_CHANNELS: usize = 3;
+const N_HDR_CHANNELS: usize = 4;
+
+/// Pixel storage of a canvas. Channels are atomics accessed with relaxed
+/// ordering, so threads writing disjoint pixels never wait on each other.
+/// Concurrent writes to the same pixel may mix channels of both colours.
+#[derive(Debug)]
+pub enum FrameBuffer {
+    /// sRGB encoded 8 bit RGB.
+    Byte(Vec<AtomicU8>),
+    /// Bits of linear, unclamped RGBA floats. The alpha channel is 1 for the
+    /// pixels that have been set and 0 elsewhere.
+    Float(Vec<AtomicU32>),
+}
 
 #[derive(Debug)]
 pub struct Canvas {
     pub width: usize,
     pub height: usize,
-    pub frame_buffer: Mutex<Vec<u8>>,
+    pub frame_buffer: FrameBuffer,
+    /// Used to quantise linear colours to bytes.
+    pub tone_mapping: ToneMapping,
 }
 
 pub fn canvas(width: usize, height: usize) -> Canvas {
@@ -19,46 +34,104 @@ pub fn canvas(width: usize, height: usize) -> Canvas {
 
 impl Canvas {
     pub fn new(width: usize, height: usize) -> Canvas {
-        let mut frame_buffer = Vec::with_capacity(width * height * N_CHANNELS);
-        frame_buffer.resize(width * height * N_CHANNELS, u8::default());
+        let frame_buffer = (0..width * height * N_CHANNELS)
+            .map(|_| AtomicU8::default())
+            .collect();
+        Canvas {
+            width,
+            height,
+            frame_buffer: FrameBuffer::Byte(frame_buffer),
+            tone_mapping: ToneMapping::default(),
+        }
+    }
+
+    /// Canvas keeping linear, unclamped radiance. Colours are only tone mapped
+    /// to bytes when they are read with `get` or exported.
+    pub fn new_hdr(width: usize, height: usize) -> Canvas {
+        let frame_buffer = (0..width * height * N_HDR_CHANNELS)
+            .map(|_| AtomicU32::new(0_f32.to_bits()))
+            .collect();
         Canvas {
             width,
             height,
-            frame_buffer: Mutex::new(frame_buffer),
+            frame_buffer: FrameBuffer::Float(frame_buffer),
+            tone_mapping: ToneMapping::default(),
         }
     }
 
+    pub fn set_tone_mapping(&mut self, tone_mapping: ToneMapping) {
+        self.tone_mapping = tone_mapping;
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        matches!(self.frame_buffer, FrameBuffer::Float(_))
+    }
+
     fn idx(&self, x: usize, y: usize) -> usize {
         debug_assert!(x < self.width);
         debug_assert!(y < self.height);
-        N_CHANNELS * (x + y * self.width)
+        x + y * self.width
     }
 
     pub fn set(&self, x: usize, y: usize, c: ColorRgbByte) {
-        let start = self.idx(x, y);
-        self.set_idx(start, c);
+        let idx = self.idx(x, y);
+        match &self.frame_buffer {
+            FrameBuffer::Byte(fb) => set_byte(fb, idx, c),
+            FrameBuffer::Float(fb) => set_float(fb, idx, c.into()),
+        }
     }
 
-    pub fn set_idx(&self, idx: usize, c: ColorRgbByte) {
-        let mut fb = self.get_frame_buffer();
-        fb[idx] = c.r;
-        fb[idx + 1] = c.g;
-        fb[idx + 2] = c.b;
+    /// Sets a linear colour, tone mapping it if the canvas stores bytes.
+    pub fn set_color(&self, x: usize, y: usize, c: ColorRgbFloat) {
+        let idx = self.idx(x, y);
+        match &self.frame_buffer {
+            FrameBuffer::Byte(fb) => set_byte(fb, idx, self.tone_mapping.to_byte(c)),
+            FrameBuffer::Float(fb) => set_float(fb, idx, c),
+        }
     }
 
     pub fn get(&self, x: usize, y: usize) -> ColorRgbByte {
-        let fb = self.get_frame_buffer();
-        let start = self.idx(x, y);
-        ColorRgbByte {
-            r: fb[start],
-            g: fb[start + 1],
-            b: fb[start + 2],
+        let idx = self.idx(x, y);
+        match &self.frame_buffer {
+            FrameBuffer::Byte(fb) => get_byte(fb, idx),
+            FrameBuffer::Float(fb) => self.tone_mapping.to_byte(get_float(fb, idx)),
+        }
+    }
+
+    /// Linear colour of a pixel.
+    pub fn get_color(&self, x: usize, y: usize) -> ColorRgbFloat {
+        let idx = self.idx(x, y);
+        match &self.frame_buffer {
+            FrameBuffer::Byte(fb) => get_byte(fb, idx).into(),
+            FrameBuffer::Float(fb) => get_float(fb, idx),
+        }
+    }
+
+    /// Coverage of a pixel. Byte canvases are fully opaque.
+    pub fn get_alpha(&self, x: usize, y: usize) -> f32 {
+        let idx = self.idx(x, y);
+        match &self.frame_buffer {
+            FrameBuffer::Byte(_) => 1.,
+            FrameBuffer::Float(fb) => load_float(&fb[idx * N_HDR_CHANNELS + 3]),
+        }
+    }
+
+    /// Tone mapped, sRGB encoded RGB bytes of the whole canvas, row by row.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match &self.frame_buffer {
+            FrameBuffer::Byte(fb) => fb.iter().map(|b| b.load(Ordering::Relaxed)).collect(),
+            FrameBuffer::Float(fb) => (0..self.width * self.height)
+                .flat_map(|idx| {
+                    let c = self.tone_mapping.to_byte(get_float(fb, idx));
+                    [c.r, c.g, c.b]
+                })
+                .collect(),
         }
     }
 
     fn buffer_as_ppm_string(&self) -> String {
-        let fb = self.get_frame_buffer();
-        fb.chunks(10)
+        self.to_bytes()
+            .chunks(10)
             .map(|chunk| {
                 chunk
                     .iter()
@@ -75,24 +148,79 @@ impl Canvas {
         header + &self.buffer_as_ppm_string() + "\n"
     }
 
-    fn get_frame_buffer(&self) -> MutexGuard<Vec<u8>> {
-        self.frame_buffer.lock().unwrap()
+    /// Saves the canvas, guessing the image format from the file extension.
+    /// `.exr` and `.hdr` files keep the linear colours, as half floats for EXR.
+    pub fn save(&self, filename: &str) -> ImageResult<()> {
+        match ImageFormat::from_path(filename) {
+            Ok(format @ (ImageFormat::OpenExr | ImageFormat::Hdr)) => {
+                self.save_with_format(filename, format)
+            }
+            _ => {
+                let image: ImageBuffer<image::Rgb<u8>, Vec<u8>> = self.into();
+                image.save(filename)
+            }
+        }
+    }
+
+    pub fn save_with_format(&self, filename: &str, format: ImageFormat) -> ImageResult<()> {
+        match format {
+            ImageFormat::OpenExr => self.save_exr(filename, ExrPrecision::Half, &[]),
+            ImageFormat::Hdr => self.save_hdr(filename),
+            _ => {
+                let image: ImageBuffer<image::Rgb<u8>, Vec<u8>> = self.into();
+                image.save_with_format(filename, format)
+            }
+        }
+    }
+}
+
+#[inline]
+fn set_byte(fb: &[AtomicU8], idx: usize, c: ColorRgbByte) {
+    let start = idx * N_CHANNELS;
+    for (channel, value) in fb[start..start + N_CHANNELS].iter().zip([c.r, c.g, c.b]) {
+        channel.store(value, Ordering::Relaxed);
     }
+}
+
+#[inline]
+fn get_byte(fb: &[AtomicU8], idx: usize) -> ColorRgbByte {
+    let start = idx * N_CHANNELS;
+    ColorRgbByte {
+        r: fb[start].load(Ordering::Relaxed),
+        g: fb[start + 1].load(Ordering::Relaxed),
+        b: fb[start + 2].load(Ordering::Relaxed),
+    }
+}
 
-    pub fn save(&self, filename: &str) {
-        let image: ImageBuffer<image::Rgb<u8>, Vec<u8>> = self.into();
-        image.save(filename).unwrap();
+#[inline]
+fn load_float(channel: &AtomicU32) -> f32 {
+    f32::from_bits(channel.load(Ordering::Relaxed))
+}
+
+#[inline]
+fn set_float(fb: &[AtomicU32], idx: usize, c: ColorRgbFloat) {
+    let start = idx * N_HDR_CHANNELS;
+    for (channel, value) in fb[start..start + N_HDR_CHANNELS]
+        .iter()
+        .zip([c.r, c.g, c.b, 1.])
+    {
+        channel.store(value.to_bits(), Ordering::Relaxed);
     }
 }
 
+#[inline]
+fn get_float(fb: &[AtomicU32], idx: usize) -> ColorRgbFloat {
+    let start = idx * N_HDR_CHANNELS;
+    color(
+        load_float(&fb[start]),
+        load_float(&fb[start + 1]),
+        load_float(&fb[start + 2]),
+    )
+}
+
 impl From<&Canvas> for ImageBuffer<image::Rgb<u8>, Vec<u8>> {
     fn from(canvas: &Canvas) -> Self {
-        let mut img = ImageBuffer::new(canvas.width as u32, canvas.height as u32);
-        for (x, y, pixel) in img.enumerate_pixels_mut() {
-            let c = canvas.get(x as usize, y as usize);
-            *pixel = image::Rgb([c.r, c.g, c.b]);
-        }
-        img
+        ImageBuffer::from_raw(canvas.width as u32, canvas.height as u32, canvas.to_bytes()).unwrap()
     }
 }
 
@@ -103,7 +231,7 @@ mod tests {
     #[test]
     fn create_canvas() {
         let can = Canvas::new(5, 3);
-        assert!(can.get_frame_buffer().iter().all(|&c| c == u8::default()));
+        assert!(can.to_bytes().iter().all(|&c| c == u8::default()));
         can.set(0, 0, color(0.5, 0., 1.).into());
         let buffer = can.to_ppm_string();
         println!("{}", buffer);
@@ -112,7 +240,7 @@ mod tests {
             "P3
 5 3
 255
-186 0 255 0 0 0 0 0 0 0
+188 0 255 0 0 0 0 0 0 0
 0 0 0 0 0 0 0 0 0 0
 0 0 0 0 0 0 0 0 0 0
 0 0 0 0 0 0 0 0 0 0
@@ -127,7 +255,69 @@ mod tests {
         canvas.set(0, 0, color(0.5, 0., 0.).into());
         canvas.set(2, 1, color(0., 0.5, 0.).into());
         assert_eq!(canvas.get(0, 0), color(0.5, 0., 0.).into());
-        canvas.save("./output/test.png");
-        std::fs::remove_file("./output/test.png").unwrap();
+        let filename = std::env::temp_dir().join("rustracer_write_image.png");
+        let filename = filename.to_str().unwrap();
+        canvas.save(filename).unwrap();
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn save_selects_format_by_extension() {
+        let canvas = Canvas::new_hdr(2, 1);
+        canvas.set_color(1, 0, color(8., 0.5, 0.));
+        for (ext, format) in [("exr", ImageFormat::OpenExr), ("hdr", ImageFormat::Hdr)] {
+            let filename = std::env::temp_dir().join(format!("rustracer_save.{}", ext));
+            let filename = filename.to_str().unwrap();
+            canvas.save(filename).unwrap();
+            let reader = image::io::Reader::open(filename).unwrap();
+            let reader = reader.with_guessed_format().unwrap();
+            assert_eq!(reader.format(), Some(format));
+            std::fs::remove_file(filename).unwrap();
+        }
+    }
+
+    #[test]
+    fn concurrent_writes() {
+        let canvas = Canvas::new_hdr(8, 8);
+        std::thread::scope(|scope| {
+            for y in 0..8 {
+                let canvas = &canvas;
+                scope.spawn(move || {
+                    for x in 0..8 {
+                        canvas.set_color(x, y, color(x as f32, y as f32, 0.));
+                    }
+                });
+            }
+        });
+        assert_relative_eq!(canvas.get_color(3, 5), color(3., 5., 0.));
+        assert_relative_eq!(canvas.get_alpha(7, 7), 1.);
+    }
+
+    #[test]
+    fn hdr_canvas_keeps_radiance() {
+        let canvas = Canvas::new_hdr(2, 2);
+        assert!(canvas.is_hdr());
+        canvas.set_color(1, 0, color(4., 0.5, -1.));
+        assert_relative_eq!(canvas.get_color(1, 0), color(4., 0.5, -1.));
+        assert_eq!(canvas.get(1, 0), color(1., 0.5, 0.).into());
+        assert_eq!(&canvas.to_bytes()[3..6], &[255, 188, 0]);
+    }
+
+    #[test]
+    fn hdr_canvas_applies_tone_mapping() {
+        let mut canvas = Canvas::new_hdr(1, 1);
+        canvas.set_color(0, 0, color(3., 1., 0.));
+        canvas.set_tone_mapping(ToneMapping::new(ToneMapOperator::Reinhard, 0.));
+        assert_eq!(canvas.get(0, 0), color(0.75, 0.5, 0.).into());
+        assert_relative_eq!(canvas.get_color(0, 0), color(3., 1., 0.));
+    }
+
+    #[test]
+    fn hdr_canvas_ppm_matches_byte_canvas() {
+        let hdr = Canvas::new_hdr(5, 3);
+        let ldr = Canvas::new(5, 3);
+        hdr.set_color(0, 0, color(0.5, 0., 1.));
+        ldr.set_color(0, 0, color(0.5, 0., 1.));
+        assert_eq!(hdr.to_ppm_string(), ldr.to_ppm_string());
     }
 }
@@ -2,8 +2,6 @@ use std::ops::{Add, Mul, Sub};
 
 use approx::{AbsDiffEq, RelativeEq};
 
-const DEFAULT_GAMMA: f32 = 2.2;
-
 type Byte = u8;
 
 #[derive(Debug, Default, Copy, Clone, PartialEq)]
@@ -29,15 +27,29 @@ pub const PURPLE: ColorRgbFloat = color(1., 0., 1.);
 pub const BLACK: ColorRgbFloat = color(0., 0., 0.);
 pub const WHITE: ColorRgbFloat = color(1., 1., 1.);
 
+/// sRGB transfer function (IEC 61966-2-1), from linear to encoded values.
 #[inline]
-fn clamp(x: f32) -> f32 {
-    let x_gamma = x.powf(1. / DEFAULT_GAMMA);
-    1.0_f32.min(0.0_f32.max(x_gamma))
+pub fn srgb_encode(x: f32) -> f32 {
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// Inverse of `srgb_encode`.
+#[inline]
+pub fn srgb_decode(x: f32) -> f32 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
 }
 
 #[inline]
 fn to_byte(x: f32) -> Byte {
-    (clamp(x) * 255.).round() as Byte
+    (srgb_encode(x.clamp(0., 1.)) * 255.).round() as Byte
 }
 
 impl From<ColorRgbFloat> for ColorRgbByte {
@@ -50,11 +62,31 @@ impl From<ColorRgbFloat> for ColorRgbByte {
     }
 }
 
+impl From<ColorRgbByte> for ColorRgbFloat {
+    fn from(c: ColorRgbByte) -> Self {
+        let linear = |b: Byte| srgb_decode(b as f32 / 255.);
+        color(linear(c.r), linear(c.g), linear(c.b))
+    }
+}
+
 #[inline]
 pub const fn color(r: f32, g: f32, b: f32) -> ColorRgbFloat {
     ColorRgbFloat { r, g, b }
 }
 
+impl ColorRgbFloat {
+    /// Relative luminance (Rec. 709 primaries)
+    #[inline]
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    #[inline]
+    pub fn max_component(&self) -> f32 {
+        self.r.max(self.g).max(self.b)
+    }
+}
+
 impl Mul<ColorRgbFloat> for ColorRgbFloat {
     type Output = Self;
 
@@ -142,6 +174,23 @@ mod tests {
         assert_relative_eq!(c1 * c2, color(0.9 * 0.7, 0.6 * 0.1, 0.75 * 0.25));
     }
 
+    #[test]
+    fn srgb_round_trip() {
+        let c = ColorRgbByte::from(color(0.5, 0.001, 1.));
+        assert_eq!(
+            c,
+            ColorRgbByte {
+                r: 188,
+                g: 3,
+                b: 255
+            }
+        );
+        for b in [0, 1, 10, 100, 188, 255] {
+            let c = ColorRgbByte { r: b, g: b, b };
+            assert_eq!(ColorRgbByte::from(ColorRgbFloat::from(c)), c);
+        }
+    }
+
     #[test]
     fn multiplying_colors_by_a_scalar() {
         let c1 = color(0.9, 0.6, 0.75);
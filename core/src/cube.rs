@@ -32,7 +32,7 @@ impl Shape for Cube {
         &mut self.base
     }
 
-    fn local_intersects(&self, ray: &Ray) -> Option<Intersection> {
+    fn local_intersects(&self, ray: &Ray) -> Option<Intersection<'_>> {
         let (xtmin, xtmax) = check_axis(ray.origin.x, ray.direction.x);
         let (ytmin, ytmax) = check_axis(ray.origin.y, ray.direction.y);
         let (ztmin, ztmax) = check_axis(ray.origin.z, ray.direction.z);
@@ -64,6 +64,22 @@ impl Shape for Cube {
             unit_vector(0., 0., local_point.z)
         }
     }
+
+    fn local_area(&self) -> f32 {
+        24.
+    }
+
+    fn local_sample(&self, u: f32, v: f32) -> Option<(Point, UnitVector)> {
+        // One of the six faces, then a point on it
+        let face = ((u * 6.) as usize).min(5);
+        let (a, b) = ((u * 6. - face as f32) * 2. - 1., v * 2. - 1.);
+        let side = if face < 3 { 1. } else { -1. };
+        Some(match face % 3 {
+            0 => (point(side, a, b), unit_vector(side, 0., 0.)),
+            1 => (point(a, side, b), unit_vector(0., side, 0.)),
+            _ => (point(a, b, side), unit_vector(0., 0., side)),
+        })
+    }
 }
 
 fn check_axis(origin: f32, direction: f32) -> (f32, f32) {
@@ -22,7 +22,7 @@ impl Cylinder {
         }
     }
 
-    fn intersect_side(&self, ray: &Ray) -> Option<Intersection> {
+    fn intersect_side(&self, ray: &Ray) -> Option<Intersection<'_>> {
         let a = ray.direction.x * ray.direction.x + ray.direction.z * ray.direction.z;
         if a.abs() < EPS {
             None
@@ -55,7 +55,7 @@ impl Cylinder {
         }
     }
 
-    fn intersect_caps(&self, ray: &Ray) -> Option<Intersection> {
+    fn intersect_caps(&self, ray: &Ray) -> Option<Intersection<'_>> {
         if !self.closed || ray.direction.y.abs() <= EPS {
             return None;
         }
@@ -95,7 +95,7 @@ impl Shape for Cylinder {
         &mut self.base
     }
 
-    fn local_intersects(&self, ray: &Ray) -> Option<Intersection> {
+    fn local_intersects(&self, ray: &Ray) -> Option<Intersection<'_>> {
         let t_cap = self.intersect_caps(ray);
         let t_side = self.intersect_side(ray);
 
@@ -9,6 +9,8 @@ pub struct Group {
     bounded_shapes: Vec<BoundedShape>,
     bounds: Bounds,
     bvh: Option<BVH>,
+    // Running totals of the areas of the shapes, to sample them
+    areas: Vec<f32>,
 }
 
 impl Debug for Group {
@@ -28,16 +30,13 @@ impl Group {
             bounded_shapes: vec![],
             bounds: no_bounds(),
             bvh: None,
+            areas: vec![],
         }
     }
 
     pub fn add_shape(&mut self, mut shape: Box<dyn Shape + Send>) {
         shape.set_parent(self);
-        let transform = &shape.get_transform();
-        self.bounds = bounds_reducer(
-            self.bounds,
-            transform_bounds(&shape.get_bounds(), transform),
-        );
+        self.bounds = bounds_reducer(self.bounds, shape.get_parent_bounds());
 
         let bounded_shape = BoundedShape::new(shape);
         self.bounded_shapes.push(bounded_shape);
@@ -56,6 +55,14 @@ impl Shape for Group {
             bs.get_shape_mut().shape_added();
         }
         self.bvh = Some(BVH::build(&mut self.bounded_shapes));
+        self.areas = self
+            .bounded_shapes
+            .iter()
+            .scan(0., |total, bs| {
+                *total += parent_area(bs.get_shape());
+                Some(*total)
+            })
+            .collect();
     }
 
     fn get_bounds(&self) -> Bounds {
@@ -70,7 +77,7 @@ impl Shape for Group {
         &mut self.base
     }
 
-    fn local_intersects(&self, ray: &Ray) -> Option<Intersection> {
+    fn local_intersects(&self, ray: &Ray) -> Option<Intersection<'_>> {
         bvh_intersects(self.bvh.as_ref().unwrap(), &self.bounded_shapes, ray)
             .filter_map(|s| s.get_shape().intersects(ray))
             .min_by(|min, x| f32::partial_cmp(&min.t, &x.t).unwrap())
@@ -79,10 +86,53 @@ impl Shape for Group {
     fn local_normal_at(&self, _local_point: &Point, _intersection: &Intersection) -> UnitVector {
         panic!("Local normal called for group.")
     }
+
+    /// Sum of the areas of the shapes in the group's space, exact for meshes
+    /// and uniformly scaled shapes. Samples make up for the others.
+    fn local_area(&self) -> f32 {
+        self.areas.last().copied().unwrap_or(0.)
+    }
+
+    fn local_sample_at(&self, u: f32, v: f32, time: f32) -> Option<(Point, UnitVector, f32)> {
+        // Picks a shape by area, and reuses `u` to sample it
+        let total = u * self.local_area();
+        let index = self.areas.partition_point(|&area| area <= total);
+        let shape = self.bounded_shapes.get(index)?.get_shape();
+        let start = if index > 0 { self.areas[index - 1] } else { 0. };
+        let picked = self.areas[index] - start;
+        let u = ((total - start) / picked).clamp(0., 1.);
+        let (local_point, local_normal, local_area) = shape.local_sample_at(u, v, time)?;
+
+        // Same ratio of areas as for the lights, |det(M)| |M^-T n|, divided
+        // by the probability of picking the shape
+        let transform_inverse = shape.get_transform_inverse_at(time);
+        let transform = transform_inverse.inverse();
+        let normal = (transform_inverse.matrix().transpose() * local_normal.to_homogeneous()).xyz();
+        let det = transform
+            .matrix()
+            .fixed_view::<3, 3>(0, 0)
+            .determinant()
+            .abs();
+        let area = local_area * det * magnitude(&normal) * self.local_area() / picked;
+        Some((transform * local_point, normalize(&normal), area))
+    }
+}
+
+// Area of `shape` in its parent's space, scaled as a uniform scale would.
+fn parent_area(shape: &dyn Shape) -> f32 {
+    let transform = shape.get_transform_inverse_at(0.).inverse();
+    let det = transform
+        .matrix()
+        .fixed_view::<3, 3>(0, 0)
+        .determinant()
+        .abs();
+    shape.local_area() * det.powf(2. / 3.)
 }
 
 #[cfg(test)]
 mod tests {
+    use std::f32::consts::PI;
+
     use super::*;
 
     #[test]
@@ -101,4 +151,56 @@ mod tests {
         let a_s = Box::new(s);
         g.add_shape(a_s);
     }
+
+    #[test]
+    fn sample_mesh_surface() {
+        let mut g = Group::default();
+        let corners = [
+            point(0., 0., 0.),
+            point(2., 0., 0.),
+            point(2., 0., 1.),
+            point(0., 0., 1.),
+        ];
+        let points = corners.map(|p| (p, None));
+        Triangle::add_to_group(&mut g, &points);
+        g.shape_added();
+        assert_relative_eq!(g.local_area(), 2.);
+        for (u, v) in [(0.1, 0.2), (0.5, 0.9), (0.99, 0.5)] {
+            let (p, n, area) = g.local_sample_at(u, v, 0.).unwrap();
+            assert_relative_eq!(area, 2.);
+            assert_relative_eq!(p.y, 0.);
+            assert!((0. ..=2.).contains(&p.x) && (0. ..=1.).contains(&p.z));
+            assert_relative_eq!(n.y.abs(), 1.);
+        }
+    }
+
+    #[test]
+    fn sample_transformed_shapes() {
+        let mut g = Group::default();
+        g.add_shape(Box::new(Sphere::new(
+            scaling(2., 2., 2.),
+            Material::default(),
+        )));
+        g.add_shape(Box::new(Sphere::new(
+            translation(5., 0., 0.) * scaling(1., 1., 3.),
+            Material::default(),
+        )));
+        g.shape_added();
+        assert_relative_eq!(g.areas[0], 16. * PI, epsilon = 1e-3);
+        // The areas the samples stand for average to the area of the group
+        let n = 100;
+        let mut sums = [0.; 2];
+        for i in 0..n {
+            for j in 0..n {
+                let (u, v) = ((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32);
+                let (p, _, area) = g.local_sample_at(u, v, 0.).unwrap();
+                sums[usize::from(p.x > 3.)] += area / (n * n) as f32;
+            }
+        }
+        assert_relative_eq!(sums[0], 16. * PI, max_relative = 0.01);
+        // Area of a prolate spheroid with semi-axes 1 and 3
+        let e = (1. - 1. / 9_f32).sqrt();
+        let spheroid = 2. * PI * (1. + 3. * e.asin() / e);
+        assert_relative_eq!(sums[1], spheroid, max_relative = 0.01);
+    }
 }
@@ -0,0 +1,187 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use ::exr::prelude::{self as exr, WritableImage};
+use image::codecs::hdr::HdrEncoder;
+use image::error::{EncodingError, ImageFormatHint};
+use image::{ImageError, ImageFormat, ImageResult};
+
+use crate::*;
+
+/// Sample type of the channels of an OpenEXR file.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ExrPrecision {
+    Half,
+    Float,
+}
+
+/// Channel written to an OpenEXR file along with RGBA, one sample per pixel
+/// row by row. Names like `normal.X` group the channels in layers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExrChannel {
+    pub name: String,
+    pub samples: Vec<f32>,
+}
+
+impl ExrChannel {
+    pub fn new(name: &str, samples: Vec<f32>) -> ExrChannel {
+        ExrChannel {
+            name: name.to_string(),
+            samples,
+        }
+    }
+}
+
+impl Canvas {
+    /// Linear RGBA of the whole canvas, row by row. Byte canvases are fully opaque.
+    pub fn to_linear_rgba(&self) -> Vec<[f32; 4]> {
+        (0..self.height)
+            .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let c = self.get_color(x, y);
+                [c.r, c.g, c.b, self.get_alpha(x, y)]
+            })
+            .collect()
+    }
+
+    /// Saves the linear RGBA channels, plus any `extra_channels`, as OpenEXR.
+    pub fn save_exr<P: AsRef<Path>>(
+        &self,
+        filename: P,
+        precision: ExrPrecision,
+        extra_channels: &[ExrChannel],
+    ) -> ImageResult<()> {
+        let rgba = self.to_linear_rgba();
+        let n_pixels = self.width * self.height;
+        let mut channels = exr::SmallVec::new();
+        for (i, name) in ["R", "G", "B", "A"].iter().enumerate() {
+            let samples = rgba.iter().map(|px| px[i]).collect::<Vec<_>>();
+            channels.push(exr_channel(name, samples, precision));
+        }
+        for channel in extra_channels {
+            if channel.samples.len() != n_pixels {
+                return Err(exr_error(format!(
+                    "channel {} has {} samples, expected {}",
+                    channel.name,
+                    channel.samples.len(),
+                    n_pixels
+                )));
+            }
+            channels.push(exr_channel(
+                &channel.name,
+                channel.samples.clone(),
+                precision,
+            ));
+        }
+
+        let layer = exr::Layer::new(
+            (self.width, self.height),
+            exr::LayerAttributes::default(),
+            exr::Encoding::FAST_LOSSLESS,
+            exr::AnyChannels::sort(channels),
+        );
+        exr::Image::from_layer(layer)
+            .write()
+            .to_file(filename)
+            .map_err(|err| match err {
+                exr::Error::Io(err) => ImageError::IoError(err),
+                err => exr_error(err),
+            })
+    }
+
+    /// Saves the linear RGB channels as Radiance HDR (RGBE).
+    pub fn save_hdr<P: AsRef<Path>>(&self, filename: P) -> ImageResult<()> {
+        let pixels = self
+            .to_linear_rgba()
+            .iter()
+            .map(|px| image::Rgb([px[0].max(0.), px[1].max(0.), px[2].max(0.)]))
+            .collect::<Vec<_>>();
+        let writer = BufWriter::new(File::create(filename)?);
+        HdrEncoder::new(writer).encode(&pixels, self.width, self.height)
+    }
+}
+
+fn exr_channel(
+    name: &str,
+    samples: Vec<f32>,
+    precision: ExrPrecision,
+) -> exr::AnyChannel<exr::FlatSamples> {
+    let samples = match precision {
+        ExrPrecision::Float => exr::FlatSamples::F32(samples),
+        ExrPrecision::Half => {
+            exr::FlatSamples::F16(samples.into_iter().map(exr::f16::from_f32).collect())
+        }
+    };
+    exr::AnyChannel::new(name, samples)
+}
+
+fn exr_error<E>(err: E) -> ImageError
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    ImageError::Encoding(EncodingError::new(
+        ImageFormatHint::Exact(ImageFormat::OpenExr),
+        err,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_canvas() -> Canvas {
+        let canvas = Canvas::new_hdr(3, 2);
+        canvas.set_color(0, 0, color(4., 0.5, 0.25));
+        canvas.set_color(2, 1, color(0., 100., 1.));
+        canvas
+    }
+
+    #[test]
+    fn exr_round_trip() {
+        let filename = std::env::temp_dir().join("rustracer_exr_round_trip.exr");
+        let canvas = test_canvas();
+        let depth = ExrChannel::new("depth.Z", vec![1., 2., 3., 4., 5., 6.]);
+        canvas
+            .save_exr(&filename, ExrPrecision::Float, &[depth])
+            .unwrap();
+
+        let image = exr::read_all_flat_layers_from_file(&filename).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+        let channels = &image.layer_data[0].channel_data.list;
+        let names = channels
+            .iter()
+            .map(|c| c.name.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["A", "B", "G", "R", "depth.Z"]);
+        assert_eq!(channels[3].sample_data.value_by_flat_index(0).to_f32(), 4.);
+        assert_eq!(
+            channels[2].sample_data.value_by_flat_index(5).to_f32(),
+            100.
+        );
+        assert_eq!(channels[0].sample_data.value_by_flat_index(1).to_f32(), 0.);
+        assert_eq!(channels[4].sample_data.value_by_flat_index(4).to_f32(), 5.);
+    }
+
+    #[test]
+    fn exr_rejects_mismatched_channels() {
+        let filename = std::env::temp_dir().join("rustracer_exr_mismatched.exr");
+        let depth = ExrChannel::new("Z", vec![1.]);
+        let res = test_canvas().save_exr(&filename, ExrPrecision::Half, &[depth]);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn hdr_round_trip() {
+        let filename = std::env::temp_dir().join("rustracer_hdr_round_trip.hdr");
+        test_canvas().save_hdr(&filename).unwrap();
+        let reader = std::io::BufReader::new(File::open(&filename).unwrap());
+        let pixels = image::codecs::hdr::HdrDecoder::new(reader)
+            .unwrap()
+            .read_image_hdr()
+            .unwrap();
+        std::fs::remove_file(&filename).unwrap();
+        assert_relative_eq!(pixels[5][1], 100., max_relative = 0.01);
+        assert_relative_eq!(pixels[0][0], 4., max_relative = 0.01);
+    }
+}
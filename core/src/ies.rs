@@ -0,0 +1,243 @@
+use std::fmt;
+
+// Largest count of values, past which f32 can't hold every integer.
+const MAX_COUNT: f32 = 16_777_216.;
+
+/// Photometric profile of a light fixture, read from an IES LM-63 file.
+///
+/// Only type C photometry is supported: vertical angles are measured from
+/// the nadir, which is the direction the fixture points to, and horizontal
+/// angles around it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IesProfile {
+    /// Vertical angles in degrees, increasing.
+    pub vertical_angles: Vec<f32>,
+    /// Horizontal angles in degrees, increasing.
+    pub horizontal_angles: Vec<f32>,
+    /// Intensities of every vertical angle for each horizontal angle, scaled
+    /// so that the brightest one is 1.
+    pub candela: Vec<f32>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum IesError {
+    MissingTilt,
+    TiltFile(String),
+    InvalidNumber(String),
+    MissingValues,
+    PhotometricType(f32),
+    Count(f32),
+    Angles,
+}
+
+impl fmt::Display for IesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IesError::MissingTilt => write!(f, "missing TILT line"),
+            IesError::TiltFile(file) => write!(f, "unsupported tilt file {}", file),
+            IesError::InvalidNumber(s) => write!(f, "invalid number {}", s),
+            IesError::MissingValues => write!(f, "missing values"),
+            IesError::PhotometricType(t) => {
+                write!(f, "unsupported photometric type {}, only type C (1) is", t)
+            }
+            IesError::Count(n) => write!(f, "invalid count {}", n),
+            IesError::Angles => write!(f, "angles must be increasing"),
+        }
+    }
+}
+
+impl IesProfile {
+    /// Parses the contents of an IES file.
+    pub fn parse(text: &str) -> Result<IesProfile, IesError> {
+        let mut lines = text.lines();
+        let tilt = lines
+            .find_map(|line| line.trim().strip_prefix("TILT="))
+            .ok_or(IesError::MissingTilt)?
+            .trim();
+        let rest = lines.collect::<Vec<_>>().join(" ");
+        let mut numbers = rest
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                s.parse::<f32>()
+                    .map_err(|_| IesError::InvalidNumber(s.to_string()))
+            });
+        let mut next = || numbers.next().unwrap_or(Err(IesError::MissingValues));
+
+        match tilt {
+            "NONE" => {}
+            "INCLUDE" => {
+                // Lamp to luminaire geometry, then the tilt angles and factors
+                next()?;
+                let count = count(next()?)?;
+                for _ in 0..2 * count {
+                    next()?;
+                }
+            }
+            file => return Err(IesError::TiltFile(file.to_string())),
+        }
+
+        // Number of lamps, lumens per lamp, candela multiplier
+        let _ = (next()?, next()?, next()?);
+        let n_vertical = count(next()?)?;
+        let n_horizontal = count(next()?)?;
+        let n_candela = n_vertical
+            .checked_mul(n_horizontal)
+            .ok_or(IesError::Count(n_vertical as f32 * n_horizontal as f32))?;
+        let photometric_type = next()?;
+        if photometric_type != 1. {
+            return Err(IesError::PhotometricType(photometric_type));
+        }
+        // Units, dimensions, ballast factor, future use and input watts
+        for _ in 0..7 {
+            next()?;
+        }
+
+        let mut read = |n| (0..n).map(|_| next()).collect::<Result<Vec<f32>, _>>();
+        let vertical_angles = read(n_vertical)?;
+        let mut horizontal_angles = read(n_horizontal)?;
+        let mut candela = read(n_candela)?;
+        let increasing =
+            |angles: &[f32]| !angles.is_empty() && angles.windows(2).all(|w| w[0] < w[1]);
+        if !increasing(&vertical_angles) || !increasing(&horizontal_angles) {
+            return Err(IesError::Angles);
+        }
+
+        // Full turns listed up to below 360° wrap around to the first angle
+        let last = horizontal_angles[n_horizontal - 1];
+        if last > 180. && last < 360. && horizontal_angles[0] == 0. {
+            horizontal_angles.push(360.);
+            candela.extend_from_within(0..n_vertical);
+        }
+        let max = candela.iter().cloned().fold(0., f32::max);
+        if max > 0. {
+            candela.iter_mut().for_each(|c| *c /= max);
+        }
+
+        Ok(IesProfile {
+            vertical_angles,
+            horizontal_angles,
+            candela,
+        })
+    }
+
+    /// Intensity relative to the brightest direction, at `vertical` radians
+    /// from the nadir and `horizontal` radians around it.
+    pub fn relative_intensity(&self, vertical: f32, horizontal: f32) -> f32 {
+        let Some((v, v_frac)) = interpolation(&self.vertical_angles, vertical.to_degrees()) else {
+            return 0.;
+        };
+        // Profiles listing part of the turn are symmetric
+        let mut h = horizontal.to_degrees().rem_euclid(360.);
+        match self.horizontal_angles.last().copied() {
+            Some(0.) => h = 0.,
+            Some(90.) => {
+                h %= 180.;
+                if h > 90. {
+                    h = 180. - h;
+                }
+            }
+            Some(180.) if h > 180. => h = 360. - h,
+            _ => {}
+        }
+        let Some((h, h_frac)) = interpolation(&self.horizontal_angles, h) else {
+            return 0.;
+        };
+
+        let n_vertical = self.vertical_angles.len();
+        let at = |h: usize, v: usize| {
+            let h = h.min(self.horizontal_angles.len() - 1);
+            self.candela[h * n_vertical + v.min(n_vertical - 1)]
+        };
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        lerp(
+            lerp(at(h, v), at(h, v + 1), v_frac),
+            lerp(at(h + 1, v), at(h + 1, v + 1), v_frac),
+            h_frac,
+        )
+    }
+}
+
+// Number of values announced by `n`, which must be a whole number.
+fn count(n: f32) -> Result<usize, IesError> {
+    if (0. ..=MAX_COUNT).contains(&n) && n.fract() == 0. {
+        Ok(n as usize)
+    } else {
+        Err(IesError::Count(n))
+    }
+}
+
+// Index of the angle below `x` and the fraction of the way to the next one,
+// or `None` outside of the angles.
+fn interpolation(angles: &[f32], x: f32) -> Option<(usize, f32)> {
+    let first = *angles.first()?;
+    let last = *angles.last()?;
+    if x < first || x > last {
+        return None;
+    }
+    let i = angles.partition_point(|&a| a <= x).saturating_sub(1);
+    match angles.get(i + 1) {
+        Some(next) => Some((i, (x - angles[i]) / (next - angles[i]))),
+        None => Some((i, 0.)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+
+    const PROFILE: &str = "IESNA:LM-63-2002
+[TEST] Downlight
+TILT=NONE
+1 1000 1 3 2 1 1 0 0 0
+1 1 50
+0 45 90
+0 90
+200 100 0
+100, 50, 0
+";
+
+    #[test]
+    fn parse_profile() {
+        let profile = IesProfile::parse(PROFILE).unwrap();
+        assert_eq!(profile.vertical_angles, vec![0., 45., 90.]);
+        assert_eq!(profile.horizontal_angles, vec![0., 90.]);
+        assert_eq!(profile.candela, vec![1., 0.5, 0., 0.5, 0.25, 0.]);
+    }
+
+    #[test]
+    fn interpolate_intensities() {
+        let profile = IesProfile::parse(PROFILE).unwrap();
+        assert_relative_eq!(profile.relative_intensity(0., 0.), 1.);
+        assert_relative_eq!(profile.relative_intensity(PI / 8., 0.), 0.75);
+        assert_relative_eq!(profile.relative_intensity(PI / 4., PI / 4.), 0.375);
+        assert_relative_eq!(profile.relative_intensity(PI, 0.), 0.);
+        // Quadrants mirror each other
+        assert_relative_eq!(profile.relative_intensity(0., PI / 2.), 0.5);
+        assert_relative_eq!(profile.relative_intensity(0., PI * 1.5), 0.5);
+        assert_relative_eq!(profile.relative_intensity(0., PI), 1.);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(IesProfile::parse("1 2 3"), Err(IesError::MissingTilt));
+        let tilt = PROFILE.replace("TILT=NONE", "TILT=lamp.tlt");
+        assert_eq!(
+            IesProfile::parse(&tilt),
+            Err(IesError::TiltFile("lamp.tlt".to_string()))
+        );
+        let truncated = &PROFILE[..PROFILE.len() - 10];
+        assert_eq!(IesProfile::parse(truncated), Err(IesError::MissingValues));
+        let type_b = PROFILE.replace("1 1000 1 3 2 1", "1 1000 1 3 2 2");
+        assert_eq!(
+            IesProfile::parse(&type_b),
+            Err(IesError::PhotometricType(2.))
+        );
+        for n in [-3., 2.5, 1e30] {
+            let count = PROFILE.replace("1 1000 1 3", &format!("1 1000 1 {}", n));
+            assert_eq!(IesProfile::parse(&count), Err(IesError::Count(n)));
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt::Debug;
+
+use rand::RngCore;
+
+use crate::*;
+
+/// Computes the light arriving at the camera along a ray. `Camera::render`
+/// takes one, so new shading algorithms can be plugged in without touching
+/// the world.
+pub trait Integrator: Debug + Send + Sync {
+    /// Radiance arriving along `ray`. Stochastic integrators return a single
+    /// sample, averaged by the camera with the other samples of the pixel.
+    fn radiance(&self, world: &World, ray: &Ray, rng: &mut dyn RngCore) -> ColorRgbFloat;
+}
+
+/// Diffuse and specular light reaching the hit from all the lights of the
+/// world, without the ambient term. The reflected and refracted rays already
+/// find the emissive shapes, so only the diffuse reflection of their light is
+/// added.
+pub fn direct_lighting(hm: &HitMaterial, world: &World) -> ColorRgbFloat {
+    let Hit {
+        point,
+        intersection,
+        ..
+    } = hm.hit;
+    world
+        .lights
+        .iter()
+        .map(|light| {
+            let samples = light.visible_samples(point, intersection.time, world);
+            let shading = |light_hit: &LightHit| match light {
+                Light::Emissive(_) => hm.diffuse_shading(light_hit),
+                _ => hm.shading(light_hit),
+            };
+            samples.iter().map(shading).sum::<ColorRgbFloat>()
+        })
+        .sum()
+}
+
+/// Shows the shading normals of the first hit, mapped from [-1, 1] to
+/// [0, 1] per component.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct DebugNormals;
+
+impl Integrator for DebugNormals {
+    fn radiance(&self, world: &World, ray: &Ray, _rng: &mut dyn RngCore) -> ColorRgbFloat {
+        match world.intersects(ray) {
+            Some(intersection) => {
+                let n = intersection.prepare_hit(ray).normalv;
+                color((n.x + 1.) / 2., (n.y + 1.) / 2., (n.z + 1.) / 2.)
+            }
+            None => BLACK,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_normals() {
+        let world = World::default();
+        let ray = Ray::new(point(0., 0., -5.), vector(0., 0., 1.));
+        let normal = DebugNormals.radiance(&world, &ray, &mut rand::thread_rng());
+        assert_relative_eq!(normal, color(0.5, 0.5, 0.));
+        let ray = Ray::new(point(0., 0., -5.), vector(0., 1., 0.));
+        assert_eq!(
+            DebugNormals.radiance(&world, &ray, &mut rand::thread_rng()),
+            BLACK
+        );
+    }
+}
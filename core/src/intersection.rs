@@ -10,6 +10,14 @@ pub struct Hit<'a> {
     pub reflectv: UnitVector,
     pub n1: f32,
     pub n2: f32,
+    /// `n1` and `n2` of the red, green and blue channels, when white light
+    /// hits a dispersive interface.
+    pub channel_indices: Option<[(f32, f32); 3]>,
+    /// Wavelength of the ray, see `Ray::wavelength`.
+    pub wavelength: Option<f32>,
+    /// Absorbing media on the sides of `n1` and `n2`.
+    pub absorption1: Option<Absorption>,
+    pub absorption2: Option<Absorption>,
 }
 
 impl Hit<'_> {
@@ -30,12 +38,42 @@ impl Hit<'_> {
         let r0 = r0 * r0;
         r0 + (1. - r0) * f32::powi(1. - cos, 5)
     }
+
+    /// Direction of the ray refracted through the surface, or `None` on total
+    /// internal reflection.
+    pub fn refracted_direction(&self) -> Option<Vector> {
+        self.refraction(self.n1, self.n2)
+    }
+
+    /// Direction refracted between media of indices `n1` and `n2`.
+    pub fn refraction(&self, n1: f32, n2: f32) -> Option<Vector> {
+        let n_ratio = n1 / n2;
+        let cos_i = dot(&self.eyev, &self.normalv);
+        let sin2_t = n_ratio * n_ratio * (1. - cos_i * cos_i);
+        if sin2_t > 1. {
+            return None;
+        }
+        let cos_t = f32::sqrt(1. - sin2_t);
+        let normal = self.normalv.into_inner();
+        Some(normal * (n_ratio * cos_i - cos_t) - self.eyev.into_inner() * n_ratio)
+    }
+
+    /// Absorbing medium on the side of the surface `direction` points to.
+    pub fn medium_towards(&self, direction: &Vector) -> Option<Absorption> {
+        if dot(direction, &self.normalv) >= 0. {
+            self.absorption1
+        } else {
+            self.absorption2
+        }
+    }
 }
 
 pub struct Intersection<'a> {
     pub t: f32,
     pub object: &'a dyn Shape,
     pub uv: Option<(f32, f32)>,
+    /// Time of the ray that hit, which places moving objects.
+    pub time: f32,
 }
 
 impl<'a> Intersection<'a> {
@@ -44,6 +82,7 @@ impl<'a> Intersection<'a> {
             t,
             object,
             uv: None,
+            time: 0.,
         }
     }
 
@@ -52,33 +91,66 @@ impl<'a> Intersection<'a> {
             t,
             object,
             uv: Some(uv),
+            time: 0.,
         }
     }
 
-    pub fn prepare_hit(&self, ray: &Ray) -> Hit {
+    /// Hit data assuming the object is surrounded by vacuum.
+    pub fn prepare_hit(&self, ray: &Ray) -> Hit<'_> {
+        let mut hit = self.prepare_hit_in(ray, &[], &[]);
+        if hit.inside {
+            std::mem::swap(&mut hit.n1, &mut hit.n2);
+            std::mem::swap(&mut hit.absorption1, &mut hit.absorption2);
+            if let Some(indices) = &mut hit.channel_indices {
+                indices
+                    .iter_mut()
+                    .for_each(|(n1, n2)| std::mem::swap(n1, n2));
+            }
+        }
+        hit
+    }
+
+    /// Hit data for a ray travelling through `containers`, the transparent
+    /// shapes enclosing it just before the hit, innermost last, and crossing
+    /// the `coincident` transparent surfaces along with the hit. See
+    /// `World::containers`.
+    pub fn prepare_hit_in(
+        &self,
+        ray: &Ray,
+        containers: &[&dyn Shape],
+        coincident: &[&dyn Shape],
+    ) -> Hit<'_> {
         let point = ray.position(self.t);
-        let object_point = self.object.get_transform_inverse() * point;
+        let object_point = self.object.get_transform_inverse_at(self.time) * point;
         let eyev = UnitVector::new_normalize(-ray.direction);
         let normalv = self.object.normal_at(&point, self);
         let inside = dot(&normalv, &eyev) < 0.;
         let normalv = if inside { -normalv } else { normalv };
         let reflectv = UnitVector::new_unchecked(reflect(&ray.direction, &normalv));
 
-        // Transparency
+        // Transparency: the ray leaves the object if it was in its
+        // containers, and enters it otherwise
         let material = self.object.get_material();
-        let (n1, n2) = if material.transparency.is_some() {
-            // TODO: nested shapes. It only works in
-            // vacuum-material interfaces.
-            let n1 = 1.;
-            let n2 = material.refractive_index;
-            if inside {
-                (n2, n1)
-            } else {
-                (n1, n2)
+        let (before, after) = if material.transparency.is_some() {
+            let mut after = containers.to_vec();
+            for shape in std::iter::once(&self.object).chain(coincident) {
+                toggle_container(&mut after, *shape);
             }
+            (containers.last().copied(), after.last().copied())
         } else {
-            (1., 1.)
+            (None, None)
+        };
+        let index = |shape: Option<&dyn Shape>, wavelength| {
+            shape.map_or(1., |s| s.get_material().refractive_index_at(wavelength))
+        };
+        let absorption =
+            |shape: Option<&dyn Shape>| shape.and_then(|s| s.get_material().absorption);
+        let dispersive = |shape: Option<&dyn Shape>| {
+            shape.is_some_and(|s| s.get_material().dispersion.is_some())
         };
+        let wavelength = ray.wavelength;
+        let channel_indices = (wavelength.is_none() && (dispersive(before) || dispersive(after)))
+            .then(|| CHANNELS.map(|(w, _)| (index(before, Some(w)), index(after, Some(w)))));
 
         Hit {
             intersection: self,
@@ -88,9 +160,23 @@ impl<'a> Intersection<'a> {
             inside,
             normalv,
             reflectv,
-            n1,
-            n2,
+            n1: index(before, wavelength),
+            n2: index(after, wavelength),
+            channel_indices,
+            wavelength,
+            absorption1: absorption(before),
+            absorption2: absorption(after),
+        }
+    }
+}
+
+/// Enters `shape`, or leaves it if it is one of the `containers` already.
+pub fn toggle_container<'a>(containers: &mut Vec<&'a dyn Shape>, shape: &'a dyn Shape) {
+    match containers.iter().position(|s| s.get_id() == shape.get_id()) {
+        Some(i) => {
+            containers.remove(i);
         }
+        None => containers.push(shape),
     }
 }
 
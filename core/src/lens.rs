@@ -0,0 +1,107 @@
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, TAU};
+
+/// Thin lens in front of the camera. Rays leave from a point of the lens and
+/// converge on the focal plane, blurring everything else.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Lens {
+    /// Radius of the lens, in world units.
+    pub aperture: f32,
+    /// Distance along the view axis to the plane in focus.
+    pub focal_distance: f32,
+    /// Number of diaphragm blades. Below 3 the aperture is round; otherwise
+    /// it is a regular polygon, which shapes the bokeh.
+    pub blades: u8,
+}
+
+impl Lens {
+    pub fn new(aperture: f32, focal_distance: f32) -> Lens {
+        Lens {
+            aperture,
+            focal_distance,
+            blades: 0,
+        }
+    }
+
+    /// Maps a point of the unit square to a point of the aperture, centred
+    /// on the optical axis.
+    pub fn sample(&self, u: f32, v: f32) -> (f32, f32) {
+        let (x, y) = if self.blades < 3 {
+            concentric_disk(u, v)
+        } else {
+            regular_polygon(self.blades, u, v)
+        };
+        (x * self.aperture, y * self.aperture)
+    }
+}
+
+/// Shirley's area preserving map from the unit square to the unit disk.
+fn concentric_disk(u: f32, v: f32) -> (f32, f32) {
+    let (a, b) = (2. * u - 1., 2. * v - 1.);
+    if a == 0. && b == 0. {
+        return (0., 0.);
+    }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, FRAC_PI_4 * (b / a))
+    } else {
+        (b, FRAC_PI_2 - FRAC_PI_4 * (a / b))
+    };
+    (r * theta.cos(), r * theta.sin())
+}
+
+/// Uniform point of a regular polygon inscribed in the unit circle. `u`
+/// picks one of the triangles between the centre and an edge.
+fn regular_polygon(blades: u8, u: f32, v: f32) -> (f32, f32) {
+    let n = blades as f32;
+    let sector = (u * n).floor().min(n - 1.);
+    let u = u * n - sector;
+    let corner = |i: f32| {
+        let angle = TAU * i / n + FRAC_PI_2;
+        (angle.cos(), angle.sin())
+    };
+    let (a, b) = (corner(sector), corner(sector + 1.));
+    // Uniform barycentric coordinates within the triangle.
+    let s = u.sqrt();
+    let (wa, wb) = (s * (1. - v), s * v);
+    (wa * a.0 + wb * b.0, wa * a.1 + wb * b.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    fn samples() -> impl Iterator<Item = (f32, f32)> {
+        (0..16).flat_map(|i| (0..16).map(move |j| ((i as f32 + 0.5) / 16., (j as f32 + 0.5) / 16.)))
+    }
+
+    #[test]
+    fn round_aperture() {
+        let lens = Lens::new(0.5, 10.);
+        assert_eq!(lens.sample(0.5, 0.5), (0., 0.));
+        for (u, v) in samples() {
+            let (x, y) = lens.sample(u, v);
+            assert!(x.hypot(y) <= 0.5 + EPS);
+        }
+        let (x, y) = lens.sample(1., 0.5);
+        assert_relative_eq!(x, 0.5);
+        assert_relative_eq!(y, 0.);
+    }
+
+    #[test]
+    fn polygonal_aperture() {
+        let lens = Lens {
+            blades: 6,
+            ..Lens::new(1., 10.)
+        };
+        // Inside the hexagon: distance to the centre along the apothem.
+        let apothem = (TAU / 12.).cos();
+        for (u, v) in samples() {
+            let (x, y) = lens.sample(u, v);
+            let angle = y.atan2(x) - FRAC_PI_2;
+            let sector_angle = angle.rem_euclid(TAU / 6.) - TAU / 12.;
+            assert!(x.hypot(y) * sector_angle.cos() <= apothem + EPS);
+        }
+        let (x, y) = lens.sample(0.16666, 0.);
+        assert_relative_eq!(x.hypot(y), 1., epsilon = 1e-3);
+    }
+}
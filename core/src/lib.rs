@@ -2,6 +2,8 @@
 #[macro_use]
 extern crate approx;
 
+pub use crate::ambient_occlusion::*;
+pub use crate::aov::*;
 pub use crate::bounds::*;
 pub use crate::camera::*;
 pub use crate::canvas::*;
@@ -10,20 +12,33 @@ pub use crate::cube::*;
 pub use crate::cylinder::*;
 pub use crate::geom::*;
 pub use crate::group::*;
+pub use crate::hdr_image::*;
+pub use crate::ies::*;
+pub use crate::integrator::*;
 pub use crate::intersection::*;
+pub use crate::lens::*;
 pub use crate::light::*;
 pub use crate::mapping::*;
 pub use crate::material::*;
+pub use crate::microfacet::*;
+pub use crate::motion::*;
 pub use crate::obj_parser::*;
+pub use crate::path_tracer::*;
 pub use crate::plane::*;
 pub use crate::ray::*;
 pub use crate::read_obj::*;
+pub use crate::render::*;
+pub use crate::sampling::*;
 pub use crate::shape::*;
 pub use crate::sphere::*;
+pub use crate::tone_map::*;
 pub use crate::transform::*;
 pub use crate::triangle::*;
+pub use crate::whitted::*;
 pub use crate::world::*;
 
+mod ambient_occlusion;
+mod aov;
 mod bounds;
 mod camera;
 mod canvas;
@@ -32,18 +47,29 @@ mod cube;
 mod cylinder;
 mod geom;
 mod group;
+mod hdr_image;
+mod ies;
+mod integrator;
 mod intersection;
+mod lens;
 mod light;
 mod mapping;
 mod material;
+mod microfacet;
+mod motion;
 mod obj_parser;
+mod path_tracer;
 mod plane;
 mod ray;
 mod read_obj;
+mod render;
+mod sampling;
 mod shape;
 mod sphere;
+mod tone_map;
 mod transform;
 mod triangle;
+mod whitted;
 mod world;
 
 #[cfg(test)]
@@ -1,3 +1,5 @@
+use std::f32::consts::PI;
+
 use rand::{Rng, SeedableRng};
 use rand::rngs::StdRng;
 
@@ -25,23 +27,33 @@ pub struct LightHit {
     pub distance: f32,
     pub point: Point,
     pub intensity: ColorRgbFloat,
+    pub time: f32,
 }
 
 pub enum Light {
     Point(PointLight),
+    Spot(SpotLight),
     Directional(DirectionalLight),
     Area(AreaLight),
+    Emissive(EmissiveLight),
 }
 
 impl Light {
-    pub fn lighting(&self, object_hit: &Hit, world: &World) -> ColorRgbFloat {
-        let material = object_hit.intersection.object.get_material();
-        let hm = material.get_hit_material(object_hit);
-
-        match self {
-            Light::Point(point_light) => point_light.lighting(&hm, world),
-            Light::Directional(directional_light) => directional_light.lighting(&hm, world),
-            Light::Area(area_light) => area_light.lighting(&hm, world),
+    /// Samples of the light reaching `point` at `time`, leaving out the ones
+    /// blocked by shapes of the world. The intensities of the samples add up
+    /// to the light received.
+    pub fn visible_samples(&self, point: &Point, time: f32, world: &World) -> Vec<LightHit> {
+        let light_hit = match self {
+            Light::Point(point_light) => point_light.sample(point, time),
+            Light::Spot(spot_light) => spot_light.sample(point, time),
+            Light::Directional(directional_light) => directional_light.sample(point, time),
+            Light::Area(area_light) => return area_light.visible_samples(point, time, world),
+            Light::Emissive(emissive) => return emissive.visible_samples(point, time, world),
+        };
+        if world.is_shadowed(&light_hit, None).is_none() {
+            vec![light_hit]
+        } else {
+            vec![]
         }
     }
 }
@@ -60,21 +72,14 @@ impl DirectionalLight {
         }
     }
 
-    pub fn lighting(&self, hm: &HitMaterial, world: &World) -> ColorRgbFloat {
-        let light_vector = self.direction;
-
-        let mut sum = hm.color * hm.ambient;
-        let light_hit = LightHit {
-            lightv: light_vector,
+    pub fn sample(&self, point: &Point, time: f32) -> LightHit {
+        LightHit {
+            lightv: self.direction,
             distance: f32::INFINITY,
             intensity: self.intensity,
-            point: hm.hit.point,
-        };
-
-        if world.is_shadowed(&light_hit, None).is_none() {
-            sum = sum + hm.shading(&light_hit);
+            point: *point,
+            time,
         }
-        sum
     }
 }
 
@@ -94,23 +99,92 @@ impl PointLight {
         }
     }
 
-    pub fn lighting(&self, hm: &HitMaterial, world: &World) -> ColorRgbFloat {
-        let light_vector = self.position - hm.hit.point;
+    pub fn sample(&self, point: &Point, time: f32) -> LightHit {
+        let light_vector = self.position - point;
         let distance = magnitude(&light_vector);
 
-        let mut sum = hm.color * hm.ambient;
-        let light_hit = LightHit {
+        LightHit {
             lightv: unit_vector_from_vector(light_vector / distance),
             distance,
             intensity: self.intensity,
-            point: hm.hit.point,
-        };
+            point: *point,
+            time,
+        }
+    }
+}
 
-        if world.is_shadowed(&light_hit, None).is_none() {
-            sum = sum + hm.shading(&light_hit);
+/// Point light shining in a cone around `direction`, optionally shaped by the
+/// photometric profile of a real fixture.
+#[derive(Debug)]
+pub struct SpotLight {
+    pub position: Point,
+    pub direction: UnitVector,
+    /// Intensity along `direction`, or in the brightest direction of the
+    /// profile.
+    pub intensity: ColorRgbFloat,
+    /// Half-angles of the cone in radians: the light is at full intensity
+    /// inside `inner_angle` and fades out smoothly up to `outer_angle`.
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+    pub profile: Option<IesProfile>,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Point,
+        direction: UnitVector,
+        intensity: ColorRgbFloat,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> SpotLight {
+        let outer_angle = outer_angle.clamp(0., PI);
+        SpotLight {
+            position,
+            direction,
+            intensity,
+            inner_angle: inner_angle.clamp(0., outer_angle),
+            outer_angle,
+            profile: None,
+        }
+    }
+
+    pub fn sample(&self, point: &Point, time: f32) -> LightHit {
+        let light_vector = self.position - point;
+        let distance = magnitude(&light_vector);
+        let lightv = unit_vector_from_vector(light_vector / distance);
+
+        LightHit {
+            lightv,
+            distance,
+            intensity: self.intensity * self.falloff(&-lightv.into_inner()),
+            point: *point,
+            time,
         }
-        sum
     }
+
+    /// Fraction of the intensity emitted towards the unit vector `direction`.
+    pub fn falloff(&self, direction: &Vector) -> f32 {
+        let cos = dot(&self.direction, direction).clamp(-1., 1.);
+        let cone = smoothstep(self.outer_angle.cos(), self.inner_angle.cos(), cos);
+        match &self.profile {
+            Some(profile) if cone > 0. => {
+                let (u, v) = orthonormal_basis(&self.direction);
+                let horizontal = dot(direction, &v).atan2(dot(direction, &u));
+                cone * profile.relative_intensity(cos.acos(), horizontal)
+            }
+            _ => cone,
+        }
+    }
+}
+
+// Hermite interpolation from 0 at `edge0` to 1 at `edge1`, a step when they
+// are equal.
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    if edge0 >= edge1 {
+        return if x >= edge1 { 1. } else { 0. };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0., 1.);
+    t * t * (3. - 2. * t)
 }
 
 #[derive(Debug)]
@@ -145,84 +219,138 @@ impl AreaLight {
         }
     }
 
-    pub fn lighting(&self, hm: &HitMaterial, world: &World) -> ColorRgbFloat {
-        let steps = (self.u_steps, self.v_steps);
+    /// Jittered samples of the light visible from `point`. Sampling is refined
+    /// in up to four rounds while the samples are partly in shadow.
+    pub fn visible_samples(&self, point: &Point, time: f32, world: &World) -> Vec<LightHit> {
         let u_vec = self.u_vec / (self.u_steps as f32);
         let v_vec = self.v_vec / (self.u_steps as f32);
-        let vecs = (u_vec, v_vec);
+        let light_corner_vector = self.position - point;
+        let max_rounds = 4;
+        let frac = 1.
+            / (max_rounds as f32 * self.u_steps as f32 * self.v_steps as f32 * self.jitter as f32);
 
-        let acc = BLACK;
-        self.lighting_rec(acc, hm, world, steps, vecs, 4, 4) + hm.color * hm.ambient
-    }
-
-    fn lighting_rec(
-        &self,
-        mut acc: ColorRgbFloat,
-        hm: &HitMaterial,
-        world: &World,
-        steps: (u8, u8),
-        vecs: (Vector, Vector),
-        depth: u8,
-        max_depth: u8,
-    ) -> ColorRgbFloat {
-        if depth == 0 {
-            return acc;
+        let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
+        let mut samples = vec![];
+        let mut curr_shadow_obj: Option<&dyn Shape> = None;
+
+        for round in 1..=max_rounds {
+            let mut shadowed = 0;
+            let mut not_shadowed = 0;
+            for u in 0..self.u_steps {
+                for v in 0..self.v_steps {
+                    for _ in 0..self.jitter {
+                        let ru = rng.gen_range(0.0..=1.0);
+                        let rv = rng.gen_range(0.0..=1.0);
+
+                        let light_vector =
+                            light_corner_vector + u_vec * (u as f32 + ru) + v_vec * (v as f32 + rv);
+                        let distance = magnitude(&light_vector);
+
+                        let light_hit = LightHit {
+                            lightv: unit_vector_from_vector(light_vector / distance),
+                            distance,
+                            intensity: self.intensity * frac,
+                            point: *point,
+                            time,
+                        };
+
+                        curr_shadow_obj = world.is_shadowed(&light_hit, curr_shadow_obj);
+                        if curr_shadow_obj.is_some() {
+                            shadowed += 1;
+                        } else {
+                            not_shadowed += 1;
+                            samples.push(light_hit);
+                        }
+                    }
+                }
+            }
+            if shadowed == 0 || not_shadowed == 0 {
+                // Fully lit or in umbra: the rounds taken so far are enough
+                let scale = max_rounds as f32 / round as f32;
+                for sample in &mut samples {
+                    sample.intensity = sample.intensity * scale;
+                }
+                break;
+            }
         }
+        samples
+    }
+}
 
-        let (u_steps, v_steps) = steps;
-        let (u_vec, v_vec) = vecs;
+/// Shape of the world with an emissive material, lit by `samples` points
+/// spread over its surface.
+#[derive(Debug)]
+pub struct EmissiveLight {
+    /// Index of the shape in `World::bounded_shapes`.
+    pub shape: usize,
+    pub samples: usize,
+}
 
-        let hit_point = hm.hit.point;
-        let ligh_corner_vector = self.position - hit_point;
+impl EmissiveLight {
+    pub fn new(shape: usize, samples: usize) -> EmissiveLight {
+        EmissiveLight {
+            shape,
+            samples: samples.max(1),
+        }
+    }
 
-        let jitter = self.jitter;
-        let frac = 1. / (max_depth as f32 * u_steps as f32 * v_steps as f32 * jitter as f32);
+    /// Samples of the surface visible from `point`. Their intensities follow
+    /// the emission of the surface, its area, and its orientation and
+    /// distance to the point, divided by π so that a white Phong diffuse
+    /// material reflects like a Lambertian one.
+    pub fn visible_samples(&self, point: &Point, time: f32, world: &World) -> Vec<LightHit> {
+        let shape = world.bounded_shapes[self.shape].get_shape();
+        let Some(emission) = &shape.get_material().emission else {
+            return vec![];
+        };
+        let transform_inverse = shape.get_transform_inverse_at(time);
+        let transform = transform_inverse.inverse();
+        let inverse_transpose = transform_inverse.matrix().transpose();
+        // The ratio of world to object areas at a normal n is |det(M)| |M^-T n|
+        let det = transform
+            .matrix()
+            .fixed_view::<3, 3>(0, 0)
+            .determinant()
+            .abs();
+        let scale = det / self.samples as f32;
 
         let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
-
-        let mut shadowed = 0;
-        let mut not_shadowed = 0;
-        let mut val = BLACK;
-
-        let mut curr_shadow_obj: Option<&(dyn Shape)> = None;
-
-        for u in 0..u_steps {
-            for v in 0..v_steps {
-                for _ in 0..jitter {
-                    let ru = rng.gen_range(0.0..=1.0);
-                    let rv = rng.gen_range(0.0..=1.0);
-
-                    let light_vector =
-                        ligh_corner_vector + u_vec * (u as f32 + ru) + v_vec * (v as f32 + rv);
-                    let distance = magnitude(&light_vector);
-
-                    let light_hit = LightHit {
-                        lightv: unit_vector_from_vector(light_vector / distance),
-                        distance,
-                        intensity: self.intensity * frac,
-                        point: hit_point,
-                    };
-
-                    curr_shadow_obj = world.is_shadowed(&light_hit, curr_shadow_obj);
-                    if curr_shadow_obj.is_some() {
-                        shadowed += 1;
-                    } else {
-                        not_shadowed += 1;
-                        val = val + hm.shading(&light_hit);
-                    }
-                }
+        let mut samples = vec![];
+        let mut curr_shadow_obj: Option<&dyn Shape> = None;
+        for _ in 0..self.samples {
+            let Some((local_point, local_normal, area)) =
+                shape.local_sample_at(rng.gen(), rng.gen(), time)
+            else {
+                break;
+            };
+            // Left unnormalized, so that the cosine includes |M^-T n|
+            let normal = (inverse_transpose * local_normal.to_homogeneous()).xyz();
+            let light_vector = transform * local_point - point;
+            let distance = magnitude(&light_vector);
+            let lightv = unit_vector_from_vector(light_vector / distance);
+            let cos = -dot(&normal, &lightv);
+            if cos <= 0. {
+                continue;
+            }
+            let light_hit = LightHit {
+                lightv,
+                // Stops shadow rays short of the emitting surface
+                distance: distance - EPS * 200.,
+                intensity: emission.map_at_object(&local_point)
+                    * (cos * area * scale / (PI * distance * distance)),
+                point: *point,
+                time,
+            };
+            curr_shadow_obj = world.is_shadowed(&light_hit, curr_shadow_obj);
+            if curr_shadow_obj.is_none() {
+                samples.push(light_hit);
             }
         }
-        acc = acc + val;
-        if shadowed == 0 || not_shadowed == 0 {
-            acc * ((max_depth as f32) / (1. + max_depth as f32 - depth as f32))
-        } else {
-            self.lighting_rec(acc, hm, world, steps, vecs, depth - 1, max_depth)
-        }
+        samples
     }
 }
 
-#[cfg(tests)]
+#[cfg(test)]
 mod tests {
     use super::*;
 
@@ -230,6 +358,92 @@ mod tests {
     fn point_light_creation() {
         let light = PointLight::new(point(0., 0., 0.), WHITE);
         assert_eq!(light.position, point(0., 0., 0.));
-        assert_eq!(light.intesity, WHITE);
+        assert_eq!(light.intensity, WHITE);
+    }
+
+    #[test]
+    fn spot_light_cone() {
+        let light = SpotLight::new(
+            point(0., 0., 0.),
+            unit_vector(0., -1., 0.),
+            WHITE,
+            PI / 6.,
+            PI / 4.,
+        );
+        let at = |x: f32| light.sample(&point(x, -1., 0.), 0.).intensity;
+        assert_eq!(at(0.), WHITE);
+        assert_eq!(at(0.5), WHITE);
+        assert_eq!(at(1.1), BLACK);
+        // Halfway between the cosines of the cone edges
+        let cos = ((PI / 6.).cos() + (PI / 4.).cos()) / 2.;
+        assert_relative_eq!(at(cos.acos().tan()), WHITE * 0.5, epsilon = 1e-5);
+        assert_eq!(light.sample(&point(0., 1., 0.), 0.).intensity, BLACK);
+    }
+
+    #[test]
+    fn spot_light_profile() {
+        let mut light = SpotLight::new(point(0., 0., 0.), unit_vector(0., -1., 0.), WHITE, PI, PI);
+        let profile = "TILT=NONE\n1 1000 1 2 1 1 1 0 0 0\n1 1 50\n0 90\n0\n100 20";
+        light.profile = Some(IesProfile::parse(profile).unwrap());
+        let at = |light: &SpotLight, x: f32| light.sample(&point(x, -1., 0.), 0.).intensity;
+        assert_eq!(at(&light, 0.), WHITE);
+        assert_relative_eq!(at(&light, 1.), WHITE * 0.6);
+        assert_eq!(light.sample(&point(0., 1., 0.), 0.).intensity, BLACK);
+    }
+
+    fn emissive(emission: ColorRgbFloat) -> Material {
+        Material {
+            emission: Some(Mapping::from(emission)),
+            ..Material::default()
+        }
+    }
+
+    // Irradiance over π at a point facing `normal`
+    fn received(
+        world: &World,
+        light: &EmissiveLight,
+        point: Point,
+        normal: Vector,
+    ) -> ColorRgbFloat {
+        let samples = light.visible_samples(&point, 0., world);
+        samples
+            .iter()
+            .map(|hit| hit.intensity * dot(&hit.lightv, &normal))
+            .sum()
+    }
+
+    #[test]
+    fn emissive_sphere() {
+        let sphere = Sphere::new(scaling(0.5, 0.5, 0.5), emissive(WHITE * 100.));
+        let world = World::new(vec![Box::new(sphere)], vec![]);
+        assert_eq!(world.lights.len(), 1);
+        // Seen from afar like a disc, receiving π L r² / d²
+        let light = EmissiveLight::new(0, 4096);
+        let received = received(&world, &light, point(0., 0., -5.), vector(0., 0., 1.));
+        assert_relative_eq!(received, WHITE, epsilon = 0.1);
+    }
+
+    #[test]
+    fn emissive_group_of_scaled_shapes() {
+        let mut group = Box::new(Group::new(Transform::identity(), emissive(WHITE * 100.)));
+        let sphere = Sphere::new(scaling(0.5, 0.5, 0.5), Material::default());
+        group.add_shape(Box::new(sphere));
+        let world = World::new(vec![group], vec![]);
+        // Same as the sphere on its own
+        let light = EmissiveLight::new(0, 4096);
+        let received = received(&world, &light, point(0., 0., -5.), vector(0., 0., 1.));
+        assert_relative_eq!(received, WHITE, epsilon = 0.1);
+    }
+
+    #[test]
+    fn emissive_panel() {
+        // A thin square panel of side 2 just above the point
+        let transform = translation(0., 1., 0.) * scaling(1., 0.01, 1.);
+        let panel = Cube::new(transform, emissive(WHITE));
+        let world = World::new(vec![Box::new(panel)], vec![]);
+        let light = EmissiveLight::new(0, 20000);
+        let received = received(&world, &light, point(0., 0., 0.), vector(0., 1., 0.));
+        // Form factor of the square
+        assert_relative_eq!(received, WHITE * 0.559, epsilon = 0.03);
     }
 }
@@ -1,5 +1,79 @@
 use crate::*;
 
+/// Reflectance model of a material.
+#[derive(Debug, Clone, Default)]
+pub enum Brdf {
+    /// Phong model, from the `diffuse`, `specular` and `shininess` channels.
+    #[default]
+    Phong,
+    /// GGX microfacets in the metallic/roughness workflow, with the material
+    /// `color` as base colour.
+    Microfacet {
+        metallic: Mapping<f32>,
+        roughness: Mapping<f32>,
+    },
+}
+
+// Smallest colour component of an absorbing medium, which keeps its
+// absorption coefficients finite.
+const MIN_TRANSMITTANCE: f32 = 1e-6;
+
+/// Beer-Lambert absorption of the light travelling inside a transparent
+/// solid. White light comes out as `color` after a distance of 1 at a
+/// density of 1, and the absorption grows exponentially with the density and
+/// the distance.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Absorption {
+    pub color: ColorRgbFloat,
+    pub density: f32,
+}
+
+impl Absorption {
+    pub fn new(color: ColorRgbFloat, density: f32) -> Absorption {
+        Absorption {
+            color,
+            density: density.max(0.),
+        }
+    }
+
+    /// Fraction of the light left after travelling `distance` in the medium.
+    pub fn transmittance(&self, distance: f32) -> ColorRgbFloat {
+        let depth = self.density * distance;
+        let channel = |c: f32| c.clamp(MIN_TRANSMITTANCE, 1.).powf(depth);
+        color(
+            channel(self.color.r),
+            channel(self.color.g),
+            channel(self.color.b),
+        )
+    }
+}
+
+/// Wavelengths in micrometres standing for the red, green and blue channels
+/// when dispersive materials split light, with the colour of each channel.
+/// They are the Fraunhofer C, d and F lines, at which Abbe numbers are
+/// defined.
+pub const CHANNELS: [(f32, ColorRgbFloat); 3] = [
+    (0.6563, color(1., 0., 0.)),
+    (0.5876, color(0., 1., 0.)),
+    (0.4861, color(0., 0., 1.)),
+];
+
+const C_LINE: f32 = CHANNELS[0].0;
+const D_LINE: f32 = CHANNELS[1].0;
+const F_LINE: f32 = CHANNELS[2].0;
+
+/// Variation of the refractive index with the wavelength, which makes
+/// refraction split white light.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Dispersion {
+    /// Abbe number: the lower, the larger the spread of the refractive index.
+    /// The `refractive_index` of the material is the one of the d line.
+    Abbe(f32),
+    /// Cauchy's equation `a + b / λ²`, with λ in micrometres, in place of the
+    /// `refractive_index` of the material.
+    Cauchy { a: f32, b: f32 },
+}
+
 #[derive(Debug, Clone)]
 pub struct Material {
     pub color: Mapping<ColorRgbFloat>,
@@ -7,22 +81,76 @@ pub struct Material {
     pub diffuse: Mapping<f32>,
     pub specular: Mapping<f32>,
     pub shininess: Mapping<f32>,
+    /// Light given off by the surface. Emissive shapes of the world light the
+    /// others like area lights. Planes, cylinders and cones have no area to
+    /// sample, so they only light the others through the paths of the path
+    /// tracer that hit them.
+    pub emission: Option<Mapping<ColorRgbFloat>>,
+    pub brdf: Brdf,
+    /// Spread of the reflected and refracted rays of Phong materials, from 0
+    /// for mirrors and clear glass to 1. Microfacet materials spread them by
+    /// the roughness of their brdf instead.
+    pub roughness: Mapping<f32>,
     pub reflective: Option<Mapping<f32>>,
     pub transparency: Option<Mapping<f32>>,
     pub refractive_index: f32,
+    pub dispersion: Option<Dispersion>,
+    /// Absorption of the light inside transparent materials.
+    pub absorption: Option<Absorption>,
     pub attenuation: Attenuation,
+    /// Arbitrary tag reported by the material id AOV.
+    pub id: u32,
 }
 
 impl Material {
+    /// Refractive index at a wavelength in micrometres, or for white light.
+    pub fn refractive_index_at(&self, wavelength: Option<f32>) -> f32 {
+        let wavelength = wavelength.unwrap_or(D_LINE);
+        let inverse_square = |w: f32| 1. / (w * w);
+        match self.dispersion {
+            Some(Dispersion::Abbe(abbe)) if abbe > 0. => {
+                // Cauchy's equation through the index of the d line, with
+                // the spread between the C and F lines of the Abbe number
+                let spread = (self.refractive_index - 1.) / abbe;
+                let b = spread / (inverse_square(F_LINE) - inverse_square(C_LINE));
+                self.refractive_index + b * (inverse_square(wavelength) - inverse_square(D_LINE))
+            }
+            Some(Dispersion::Cauchy { a, b }) => a + b * inverse_square(wavelength),
+            _ => self.refractive_index,
+        }
+    }
+
     pub fn get_hit_material<'a>(&self, hit: &'a Hit) -> HitMaterial<'a> {
         let object_point = &hit.object_point;
+        let color = self.color.map_at_object(object_point);
+        let microfacet = match &self.brdf {
+            Brdf::Phong => None,
+            Brdf::Microfacet {
+                metallic,
+                roughness,
+            } => Some(Microfacet::new(
+                color,
+                metallic.map_at_object(object_point),
+                roughness.map_at_object(object_point),
+            )),
+        };
+        let roughness = match &microfacet {
+            Some(microfacet) => microfacet.roughness,
+            None => self.roughness.map_at_object(object_point),
+        };
         HitMaterial {
             hit,
-            color: self.color.map_at_object(object_point),
+            color,
             ambient: self.ambient.map_at_object(object_point),
             diffuse: self.diffuse.map_at_object(object_point),
             specular: self.specular.map_at_object(object_point),
             shininess: self.shininess.map_at_object(object_point),
+            emission: self
+                .emission
+                .as_ref()
+                .map_or(BLACK, |emission| emission.map_at_object(object_point)),
+            roughness,
+            microfacet,
         }
     }
 }
@@ -35,10 +163,16 @@ impl Default for Material {
             diffuse: Mapping::from(0.9),
             specular: Mapping::from(0.9),
             shininess: Mapping::from(200.),
+            emission: None,
+            brdf: Brdf::Phong,
+            roughness: Mapping::from(0.),
             reflective: None,
             transparency: None,
             refractive_index: 1.0,
+            dispersion: None,
+            absorption: None,
             attenuation: Attenuation::None,
+            id: 0,
         }
     }
 }
@@ -50,6 +184,11 @@ pub struct HitMaterial<'a> {
     pub diffuse: f32,
     pub specular: f32,
     pub shininess: f32,
+    pub emission: ColorRgbFloat,
+    /// Spread of the reflected and refracted rays.
+    pub roughness: f32,
+    /// Microfacet reflectance, replacing Phong's.
+    pub microfacet: Option<Microfacet>,
 }
 
 impl<'a> HitMaterial<'a> {
@@ -60,6 +199,9 @@ impl<'a> HitMaterial<'a> {
         }: &LightHit,
     ) -> ColorRgbFloat {
         let Hit { eyev, normalv, .. } = self.hit;
+        if let Some(microfacet) = &self.microfacet {
+            return microfacet.reflected(normalv, eyev, &lightv) * intensity;
+        }
         let light_dot_normal = dot(&lightv, normalv);
         let mut total = BLACK;
         if light_dot_normal > 0. {
@@ -73,4 +215,44 @@ impl<'a> HitMaterial<'a> {
         }
         total
     }
+
+    /// The diffuse part of `shading`, without highlights.
+    pub fn diffuse_shading(
+        &self,
+        &LightHit {
+            lightv, intensity, ..
+        }: &LightHit,
+    ) -> ColorRgbFloat {
+        let Hit { eyev, normalv, .. } = self.hit;
+        if let Some(microfacet) = &self.microfacet {
+            return microfacet.diffuse_reflected(normalv, eyev, &lightv) * intensity;
+        }
+        let light_dot_normal = dot(&lightv, normalv);
+        if light_dot_normal > 0. {
+            self.color * intensity * self.diffuse * light_dot_normal
+        } else {
+            BLACK
+        }
+    }
+
+    /// Colour of the light scattered diffusely by the surface.
+    pub fn diffuse_albedo(&self) -> ColorRgbFloat {
+        match &self.microfacet {
+            Some(microfacet) => microfacet.diffuse_albedo(self.n_dot_v()),
+            None => self.color * self.diffuse,
+        }
+    }
+
+    /// Colour of the light reflected from the mirror direction by the
+    /// microfacets, on top of the material's `reflective` channel.
+    pub fn specular_reflectance(&self) -> ColorRgbFloat {
+        match &self.microfacet {
+            Some(microfacet) => microfacet.specular_reflectance(self.n_dot_v()),
+            None => BLACK,
+        }
+    }
+
+    fn n_dot_v(&self) -> f32 {
+        dot(&self.hit.eyev, &self.hit.normalv)
+    }
 }
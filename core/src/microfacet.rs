@@ -0,0 +1,179 @@
+use std::f32::consts::PI;
+
+use crate::*;
+
+/// Fresnel reflectance of dielectrics at normal incidence in the
+/// metallic/roughness workflow.
+const DIELECTRIC_F0: f32 = 0.04;
+
+// Lower bound of the GGX alpha, keeping highlights of smooth surfaces finite.
+const MIN_ALPHA: f32 = 1e-3;
+
+/// GGX (Trowbridge-Reitz) microfacet reflectance at a hit, with Smith
+/// shadowing and Schlick's Fresnel, in the metallic/roughness workflow.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Microfacet {
+    pub base_color: ColorRgbFloat,
+    pub metallic: f32,
+    pub roughness: f32,
+}
+
+impl Microfacet {
+    pub fn new(base_color: ColorRgbFloat, metallic: f32, roughness: f32) -> Microfacet {
+        Microfacet {
+            base_color,
+            metallic: metallic.clamp(0., 1.),
+            roughness: roughness.clamp(0., 1.),
+        }
+    }
+
+    /// Reflectance at normal incidence: 4% for dielectrics, the base colour
+    /// for metals.
+    pub fn f0(&self) -> ColorRgbFloat {
+        WHITE * (DIELECTRIC_F0 * (1. - self.metallic)) + self.base_color * self.metallic
+    }
+
+    fn alpha(&self) -> f32 {
+        (self.roughness * self.roughness).max(MIN_ALPHA)
+    }
+
+    /// Light reflected towards `eyev` for a light of unit intensity coming
+    /// from `lightv`. This is the BRDF times the cosine to the light, scaled
+    /// by π so that a white diffuse material matches Phong's `diffuse: 1`.
+    pub fn reflected(
+        &self,
+        normalv: &UnitVector,
+        eyev: &UnitVector,
+        lightv: &UnitVector,
+    ) -> ColorRgbFloat {
+        let (diffuse, specular) = self.lobes(normalv, eyev, lightv);
+        diffuse + specular
+    }
+
+    /// Part of `reflected` scattered diffusely, without the light reflected
+    /// by the microfacets.
+    pub fn diffuse_reflected(
+        &self,
+        normalv: &UnitVector,
+        eyev: &UnitVector,
+        lightv: &UnitVector,
+    ) -> ColorRgbFloat {
+        self.lobes(normalv, eyev, lightv).0
+    }
+
+    // Diffuse and specular terms of `reflected`.
+    fn lobes(
+        &self,
+        normalv: &UnitVector,
+        eyev: &UnitVector,
+        lightv: &UnitVector,
+    ) -> (ColorRgbFloat, ColorRgbFloat) {
+        let n_dot_l = dot(normalv, lightv);
+        let n_dot_v = dot(normalv, eyev);
+        if n_dot_l <= 0. || n_dot_v <= 0. {
+            return (BLACK, BLACK);
+        }
+        let halfv = normalize(&(lightv.into_inner() + eyev.into_inner()));
+        let n_dot_h = dot(normalv, &halfv).max(0.);
+        let v_dot_h = dot(eyev, &halfv).max(0.);
+
+        let alpha = self.alpha();
+        let fresnel = schlick_fresnel(self.f0(), v_dot_h);
+        let specular = fresnel
+            * (ggx_distribution(n_dot_h, alpha) * smith_shadowing(n_dot_l, n_dot_v, alpha)
+                / (4. * n_dot_l * n_dot_v));
+        let diffuse = (WHITE - fresnel) * self.base_color * ((1. - self.metallic) / PI);
+        (diffuse * (PI * n_dot_l), specular * (PI * n_dot_l))
+    }
+
+    /// Diffuse albedo seen from `n_dot_v`, once the light reflected by the
+    /// microfacets is taken out.
+    pub fn diffuse_albedo(&self, n_dot_v: f32) -> ColorRgbFloat {
+        (WHITE - self.specular_reflectance(n_dot_v)) * self.base_color * (1. - self.metallic)
+    }
+
+    /// Fraction of the light from the mirror direction reflected towards a
+    /// viewer at `n_dot_v`. The Fresnel gain at grazing angles is reduced on
+    /// rough surfaces, which spread it over a wider lobe.
+    pub fn specular_reflectance(&self, n_dot_v: f32) -> ColorRgbFloat {
+        let f0 = self.f0();
+        let grazing = (1. - self.roughness).max(f0.max_component());
+        let weight = (1. - n_dot_v.clamp(0., 1.)).powi(5);
+        f0 + (WHITE * grazing - f0) * weight
+    }
+}
+
+/// GGX normal distribution.
+pub fn ggx_distribution(n_dot_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.) + 1.;
+    a2 / (PI * d * d)
+}
+
+/// Smith's height-uncorrelated masking and shadowing for GGX.
+pub fn smith_shadowing(n_dot_l: f32, n_dot_v: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let g1 = |cos: f32| 2. * cos / (cos + (a2 + (1. - a2) * cos * cos).sqrt());
+    g1(n_dot_l) * g1(n_dot_v)
+}
+
+/// Schlick's approximation of the Fresnel reflectance.
+pub fn schlick_fresnel(f0: ColorRgbFloat, cos: f32) -> ColorRgbFloat {
+    f0 + (WHITE - f0) * (1. - cos.clamp(0., 1.)).powi(5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ggx_distribution_is_normalized() {
+        // The projected distribution integrates to 1 over the hemisphere
+        for alpha in [0.1, 0.5, 1.] {
+            let steps = 20000;
+            let integral: f32 = (0..steps)
+                .map(|i| {
+                    let theta = (i as f32 + 0.5) / steps as f32 * PI / 2.;
+                    let (sin, cos) = theta.sin_cos();
+                    ggx_distribution(cos, alpha) * cos * sin * 2. * PI * (PI / 2. / steps as f32)
+                })
+                .sum();
+            assert_relative_eq!(integral, 1., epsilon = 1e-2);
+        }
+    }
+
+    #[test]
+    fn rough_dielectric_is_mostly_diffuse() {
+        let microfacet = Microfacet::new(color(1., 0., 0.), 0., 1.);
+        let n = unit_vector(0., 0., 1.);
+        let reflected = microfacet.reflected(&n, &n, &n);
+        assert!(reflected.r > 0.9 && reflected.r < 1.);
+        assert!(reflected.g > 0. && reflected.g < 0.05);
+        let below = unit_vector(0., 0., -1.);
+        assert_eq!(microfacet.reflected(&n, &n, &below), BLACK);
+    }
+
+    #[test]
+    fn metals_reflect_their_color() {
+        let gold = Microfacet::new(color(1., 0.8, 0.3), 1., 0.3);
+        assert_eq!(gold.f0(), color(1., 0.8, 0.3));
+        assert_eq!(gold.diffuse_albedo(1.), BLACK);
+        let n = unit_vector(0., 0., 1.);
+        let eye = normalize(&vector(0.6, 0., 0.8));
+        let mirror = normalize(&vector(-0.6, 0., 0.8));
+        let off = normalize(&vector(0., 0.6, 0.8));
+        let highlight = gold.reflected(&n, &eye, &mirror);
+        assert!(highlight.r > highlight.b);
+        assert!(highlight.r > gold.reflected(&n, &eye, &off).r);
+    }
+
+    #[test]
+    fn fresnel_grows_at_grazing_angles() {
+        let f0 = WHITE * DIELECTRIC_F0;
+        assert_relative_eq!(schlick_fresnel(f0, 1.), f0);
+        assert_relative_eq!(schlick_fresnel(f0, 0.), WHITE);
+        let smooth = Microfacet::new(WHITE, 0., 0.);
+        let rough = Microfacet::new(WHITE, 0., 1.);
+        assert!(smooth.specular_reflectance(0.).r > rough.specular_reflectance(0.).r);
+    }
+}
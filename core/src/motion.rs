@@ -0,0 +1,247 @@
+use nalgebra::{Matrix3, Matrix4, Rotation3, UnitQuaternion};
+
+use crate::*;
+
+/// Number of steps per keyframe interval used to bound the motion.
+const BOUNDS_STEPS: usize = 32;
+
+/// Affine transform split into translation, rotation and stretch (scale and
+/// shear), which interpolate without the shrinking of a matrix lerp.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DecomposedTransform {
+    pub translation: Vector,
+    pub rotation: UnitQuaternion<f32>,
+    pub stretch: Matrix3<f32>,
+}
+
+impl DecomposedTransform {
+    /// Polar decomposition of the linear part of `transform`. The projective
+    /// row is ignored.
+    pub fn new(transform: &Transform) -> DecomposedTransform {
+        let m = transform.matrix();
+        let translation = vector(m[(0, 3)], m[(1, 3)], m[(2, 3)]);
+        let linear: Matrix3<f32> = m.fixed_view::<3, 3>(0, 0).into_owned();
+
+        // Averaging a matrix with its inverse transpose converges to its
+        // closest orthogonal matrix.
+        let mut rotation = linear;
+        for _ in 0..100 {
+            let next = match rotation.transpose().try_inverse() {
+                Some(inverse_transpose) => (rotation + inverse_transpose) * 0.5,
+                None => break,
+            };
+            let change = (next - rotation).abs().max();
+            rotation = next;
+            if change < 1e-6 {
+                break;
+            }
+        }
+        // Keep mirroring in the stretch, so the rotation is a proper one.
+        if rotation.determinant() < 0. {
+            rotation = -rotation;
+        }
+        let stretch = rotation.transpose() * linear;
+        let rotation = Rotation3::from_matrix_unchecked(rotation);
+        DecomposedTransform {
+            translation,
+            rotation: UnitQuaternion::from_rotation_matrix(&rotation),
+            stretch,
+        }
+    }
+
+    /// Translation and stretch are interpolated linearly and the rotation
+    /// along the shortest arc.
+    pub fn interpolate(&self, other: &DecomposedTransform, t: f32) -> DecomposedTransform {
+        let mut to = other.rotation;
+        if self.rotation.coords.dot(&to.coords) < 0. {
+            to = UnitQuaternion::new_unchecked(-to.into_inner());
+        }
+        DecomposedTransform {
+            translation: self.translation.lerp(&other.translation, t),
+            rotation: self.rotation.slerp(&to, t),
+            stretch: self.stretch * (1. - t) + other.stretch * t,
+        }
+    }
+
+    pub fn to_transform(&self) -> Transform {
+        let linear = self.rotation.to_rotation_matrix().into_inner() * self.stretch;
+        let mut m = Matrix4::identity();
+        m.fixed_view_mut::<3, 3>(0, 0).copy_from(&linear);
+        m.fixed_view_mut::<3, 1>(0, 3).copy_from(&self.translation);
+        Transform::from_matrix_unchecked(m)
+    }
+
+    /// Inverse of `to_transform`, built from the parts instead of inverting
+    /// the matrix. `None` if the stretch is singular.
+    pub fn to_inverse_transform(&self) -> Option<Transform> {
+        let rotation = self.rotation.to_rotation_matrix().into_inner();
+        let linear = self.stretch.try_inverse()? * rotation.transpose();
+        let translation = -linear * self.translation;
+        let mut m = Matrix4::identity();
+        m.fixed_view_mut::<3, 3>(0, 0).copy_from(&linear);
+        m.fixed_view_mut::<3, 1>(0, 3).copy_from(&translation);
+        Some(Transform::from_matrix_unchecked(m))
+    }
+}
+
+/// Transform changing over time, interpolated between keyframes. Before the
+/// first and after the last keyframe it holds still.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimatedTransform {
+    keyframes: Vec<(f32, DecomposedTransform)>,
+    // Inverses of the keyframes, which rays outside of the motion and rays
+    // at a keyframe use as they are
+    inverses: Vec<Transform>,
+}
+
+impl AnimatedTransform {
+    /// Keyframes are `(time, transform)` pairs, in any order. Returns `None`
+    /// if there are none.
+    pub fn new(keyframes: &[(f32, Transform)]) -> Option<AnimatedTransform> {
+        if keyframes.is_empty() {
+            return None;
+        }
+        let mut keyframes = keyframes
+            .iter()
+            .map(|(time, transform)| (*time, DecomposedTransform::new(transform)))
+            .collect::<Vec<_>>();
+        keyframes.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let inverses = keyframes
+            .iter()
+            .map(|(_, keyframe)| keyframe.to_transform().inverse())
+            .collect();
+        Some(AnimatedTransform {
+            keyframes,
+            inverses,
+        })
+    }
+
+    pub fn at(&self, time: f32) -> Transform {
+        match self.interval(time) {
+            Ok((i, t)) => self.keyframes[i]
+                .1
+                .interpolate(&self.keyframes[i + 1].1, t)
+                .to_transform(),
+            Err(i) => self.keyframes[i].1.to_transform(),
+        }
+    }
+
+    /// Same as `at(time).inverse()`, without inverting a matrix.
+    pub fn inverse_at(&self, time: f32) -> Transform {
+        match self.interval(time) {
+            Ok((i, t)) => {
+                let interpolated = self.keyframes[i].1.interpolate(&self.keyframes[i + 1].1, t);
+                interpolated
+                    .to_inverse_transform()
+                    .unwrap_or_else(|| interpolated.to_transform().inverse())
+            }
+            Err(i) => self.inverses[i],
+        }
+    }
+
+    // Keyframe `i` and the fraction of the way to the next one at `time`, or
+    // `Err(i)` when keyframe `i` is held.
+    fn interval(&self, time: f32) -> Result<(usize, f32), usize> {
+        let next = self.keyframes.partition_point(|(t, _)| *t <= time);
+        if next == 0 {
+            return Err(0);
+        }
+        let (t0, _) = self.keyframes[next - 1];
+        if next == self.keyframes.len() || t0 == time {
+            return Err(next - 1);
+        }
+        let (t1, _) = self.keyframes[next];
+        Ok((next - 1, (time - t0) / (t1 - t0)))
+    }
+
+    /// Bounds of `bounds` swept along the motion, sampled at regular steps.
+    pub fn transform_bounds(&self, bounds: &Bounds) -> Bounds {
+        let mut swept = transform_bounds(bounds, &self.keyframes[0].1.to_transform());
+        for pair in self.keyframes.windows(2) {
+            let (t0, t1) = (pair[0].0, pair[1].0);
+            for step in 1..=BOUNDS_STEPS {
+                let time = t0 + (t1 - t0) * step as f32 / BOUNDS_STEPS as f32;
+                swept = bounds_reducer(swept, transform_bounds(bounds, &self.at(time)));
+            }
+        }
+        swept
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+
+    use super::*;
+
+    #[test]
+    fn decompose_and_recompose() {
+        let transform = translation(1., 2., 3.) * rotation_y(0.7) * scaling(2., 1., 0.5);
+        let decomposed = DecomposedTransform::new(&transform);
+        assert_relative_eq!(decomposed.translation, vector(1., 2., 3.));
+        assert_relative_eq!(decomposed.rotation.angle(), 0.7, epsilon = 1e-5);
+        let stretch = Matrix3::new(2., 0., 0., 0., 1., 0., 0., 0., 0.5);
+        assert_relative_eq!(decomposed.stretch, stretch, epsilon = 1e-5);
+        for transform in [transform, rotation_x(1.) * scaling(1., -1., 1.)] {
+            let recomposed = DecomposedTransform::new(&transform).to_transform();
+            assert_relative_eq!(recomposed.matrix(), transform.matrix(), epsilon = 1e-5);
+        }
+    }
+
+    #[test]
+    fn rotation_is_interpolated_not_lerped() {
+        let motion =
+            AnimatedTransform::new(&[(0., Transform::identity()), (1., rotation_z(FRAC_PI_2))])
+                .unwrap();
+        // A matrix lerp would shrink the point towards the axis.
+        let p = motion.at(0.5) * point(1., 0., 0.);
+        assert_relative_eq!(
+            p,
+            point(FRAC_PI_4.cos(), FRAC_PI_4.sin(), 0.),
+            epsilon = 1e-5
+        );
+    }
+
+    #[test]
+    fn holds_outside_keyframes() {
+        let motion = AnimatedTransform::new(&[
+            (1., translation(1., 0., 0.)),
+            (0., Transform::identity()),
+            (2., translation(1., 4., 0.)),
+        ])
+        .unwrap();
+        let origin = point(0., 0., 0.);
+        assert_relative_eq!(motion.at(-1.) * origin, origin);
+        assert_relative_eq!(motion.at(0.5) * origin, point(0.5, 0., 0.));
+        assert_relative_eq!(motion.at(1.5) * origin, point(1., 2., 0.));
+        assert_relative_eq!(motion.at(3.) * origin, point(1., 4., 0.));
+        assert!(AnimatedTransform::new(&[]).is_none());
+    }
+
+    #[test]
+    fn inverse_matches_the_transform() {
+        let motion = AnimatedTransform::new(&[
+            (0., translation(1., 2., 3.) * scaling(2., 1., 1.)),
+            (1., rotation_y(1.) * scaling(1., 0.5, -1.)),
+        ])
+        .unwrap();
+        for time in [-1., 0., 0.3, 1., 2.] {
+            let product = motion.at(time) * motion.inverse_at(time);
+            assert_relative_eq!(product.matrix(), &Matrix4::identity(), epsilon = 1e-5);
+        }
+    }
+
+    #[test]
+    fn bounds_cover_the_motion() {
+        let motion = AnimatedTransform::new(&[
+            (0., translation(-2., 0., 0.)),
+            (1., translation(2., 0., 0.) * rotation_z(FRAC_PI_2)),
+        ])
+        .unwrap();
+        let (min, max) = motion.transform_bounds(&(point(-1., -1., -1.), point(1., 1., 1.)));
+        assert_relative_eq!(min.x, -3., epsilon = 1e-5);
+        assert_relative_eq!(max.x, 3., epsilon = 1e-5);
+        // Halfway through, the rotated cube's corners stick out
+        assert!(max.y > 1.4);
+    }
+}
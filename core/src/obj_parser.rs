@@ -23,9 +23,6 @@ struct VertexNormalCommand(UnitVector);
 #[derive(Debug, PartialEq)]
 struct FaceCommand(Face);
 
-#[derive(Debug, PartialEq)]
-struct GroupCommand(GroupName);
-
 #[derive(Debug, PartialEq)]
 pub struct FaceVertex {
     pub idx: usize,
@@ -0,0 +1,302 @@
+use rand::{Rng, RngCore};
+
+use crate::*;
+
+/// Monte Carlo path tracer. Diffuse surfaces scatter rays with a cosine
+/// weighted distribution, the lights are sampled directly at each vertex,
+/// and paths are ended by Russian roulette after `roulette_depth` bounces.
+/// Emissive shapes are sampled for their diffuse reflection only, their
+/// highlights and mirror images are left to the paths that hit them.
+/// Unlike the Whitted tracer it ignores the ambient term of the materials,
+/// which the indirect light replaces.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PathTracer {
+    pub max_bounces: u8,
+    pub roulette_depth: u8,
+}
+
+impl Default for PathTracer {
+    fn default() -> PathTracer {
+        PathTracer {
+            max_bounces: 8,
+            roulette_depth: 3,
+        }
+    }
+}
+
+// Minimum survival probability of Russian roulette.
+const MIN_SURVIVAL: f32 = 0.05;
+
+impl PathTracer {
+    pub fn new(max_bounces: u8) -> PathTracer {
+        PathTracer {
+            max_bounces,
+            ..PathTracer::default()
+        }
+    }
+}
+
+impl Integrator for PathTracer {
+    /// Radiance arriving along `ray`, estimated with a single path.
+    fn radiance(&self, world: &World, ray: &Ray, rng: &mut dyn RngCore) -> ColorRgbFloat {
+        let mut radiance = BLACK;
+        let mut throughput = WHITE;
+        let mut ray = Ray::new_with_time(ray.origin, ray.direction, ray.time);
+        let mut medium: Option<Absorption> = None;
+        // Emitters sampled as lights at a diffuse vertex are already counted
+        // when the path reaches them from it
+        let mut diffuse = false;
+
+        for bounce in 0..=self.max_bounces {
+            let Some(intersection) = world.intersects(&ray) else {
+                break;
+            };
+            if let Some(medium) = &medium {
+                let distance = intersection.t * ray.direction.norm();
+                throughput = throughput * medium.transmittance(distance);
+            }
+            let hit = world.prepare_hit(&intersection, &ray, medium);
+            let material = hit.intersection.object.get_material();
+            let hm = material.get_hit_material(&hit);
+            let sampled = diffuse && world.is_sampled_emitter(hit.intersection.object);
+            if !sampled {
+                radiance = radiance + throughput * hm.emission;
+            }
+            radiance = radiance + throughput * direct_lighting(&hm, world);
+
+            if bounce == self.max_bounces {
+                break;
+            }
+            let Some((weight, next, diffuse_bounce)) = scatter(&hit, rng) else {
+                break;
+            };
+            throughput = throughput * weight;
+            diffuse = diffuse_bounce;
+            medium = hit.medium_towards(&next.direction);
+            ray = next;
+
+            if bounce + 1 >= self.roulette_depth {
+                let survival = throughput.max_component().clamp(MIN_SURVIVAL, 1.);
+                if rng.gen::<f32>() >= survival {
+                    break;
+                }
+                throughput = throughput * (1. / survival);
+            }
+        }
+        radiance
+    }
+}
+
+/// Picks one of the diffuse, reflected and refracted continuations of the
+/// path with a probability proportional to its strength. Returns the factor
+/// applied to the path throughput, the scattered ray, and whether it was
+/// scattered diffusely.
+fn scatter(hit: &Hit, rng: &mut dyn RngCore) -> Option<(ColorRgbFloat, Ray, bool)> {
+    let material = hit.intersection.object.get_material();
+    let hm = material.get_hit_material(hit);
+    let object_point = &hit.object_point;
+    let albedo = hm.diffuse_albedo();
+    let reflective = material
+        .reflective
+        .as_ref()
+        .map_or(0., |reflective| reflective.map_at_object(object_point));
+    let mut transparency = material
+        .transparency
+        .as_ref()
+        .map_or(0., |transparency| transparency.map_at_object(object_point));
+    let mut mirror = reflective;
+    if material.reflective.is_some() && material.transparency.is_some() {
+        let reflectance = hit.schlick();
+        mirror *= reflectance;
+        transparency *= 1. - reflectance;
+    }
+    let refracted = hit.refracted_direction();
+    if refracted.is_none() {
+        // Total internal reflection
+        mirror += transparency;
+        transparency = 0.;
+    }
+    let specular = hm.specular_reflectance() + WHITE * mirror;
+
+    let (diffuse, reflect) = (albedo.max_component(), specular.max_component());
+    let total = diffuse + reflect + transparency;
+    if total <= 0. {
+        return None;
+    }
+
+    let time = hit.intersection.time;
+    let new_ray = |origin: Point, direction: Vector, wavelength| {
+        Ray::new_with_time(origin + direction * EPS * 100., direction, time)
+            .with_wavelength(wavelength)
+    };
+    let pick = rng.gen::<f32>() * total;
+    if pick < diffuse {
+        let direction = cosine_hemisphere(&hit.normalv, rng.gen(), rng.gen()).into_inner();
+        let ray = new_ray(hit.point, direction, hit.wavelength);
+        Some((albedo * (total / diffuse), ray, true))
+    } else if pick < diffuse + reflect {
+        let direction = spread(&hm, hit.reflectv.into_inner(), rng)?;
+        let ray = new_ray(hit.point, direction, hit.wavelength);
+        Some((specular * (total / reflect), ray, false))
+    } else {
+        let origin = hit.point - hit.normalv.into_inner() * EPS;
+        match hit.channel_indices {
+            // Dispersion: the path continues with one of the channels
+            Some(indices) => {
+                let channel = rng.gen_range(0..CHANNELS.len());
+                let (wavelength, mask) = CHANNELS[channel];
+                let (n1, n2) = indices[channel];
+                let direction = spread(&hm, hit.refraction(n1, n2)?, rng)?;
+                let weight = mask * (total * CHANNELS.len() as f32);
+                Some((weight, new_ray(origin, direction, Some(wavelength)), false))
+            }
+            None => {
+                let direction = spread(&hm, refracted?, rng)?;
+                Some((
+                    WHITE * total,
+                    new_ray(origin, direction, hit.wavelength),
+                    false,
+                ))
+            }
+        }
+    }
+}
+
+/// Spreads a reflected or refracted direction by the roughness of the hit.
+/// Returns `None` if it crosses to the other side of the surface.
+fn spread(hm: &HitMaterial, direction: Vector, rng: &mut dyn RngCore) -> Option<Vector> {
+    if hm.roughness <= 0. {
+        return Some(direction);
+    }
+    let axis = normalize(&direction);
+    let spread = glossy_lobe(&axis, hm.roughness, rng.gen(), rng.gen());
+    let normal = &hm.hit.normalv;
+    (dot(&spread, normal) * dot(&axis, normal) > 0.).then(|| spread.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn mean_radiance(world: &World, ray: &Ray, samples: usize) -> ColorRgbFloat {
+        let tracer = PathTracer::default();
+        let mut rng = StdRng::seed_from_u64(7);
+        let sum: ColorRgbFloat = (0..samples)
+            .map(|_| tracer.radiance(world, ray, &mut rng))
+            .sum();
+        sum * (1. / samples as f32)
+    }
+
+    // A white floor lit from above and a red wall standing on it, which only
+    // receives the light bounced off the floor.
+    fn bleeding_world() -> World {
+        let floor = Plane::new(
+            Transform::identity(),
+            Material {
+                specular: Mapping::from(0.),
+                ..Material::default()
+            },
+        );
+        let wall = Plane::new(
+            translation(0., 0., 5.) * rotation_x(std::f32::consts::FRAC_PI_2),
+            Material {
+                color: Mapping::from(color(1., 0., 0.)),
+                specular: Mapping::from(0.),
+                ..Material::default()
+            },
+        );
+        let light = Light::Directional(DirectionalLight::new(unit_vector(0., 1., 0.), WHITE));
+        World::new(vec![Box::new(floor), Box::new(wall)], vec![light])
+    }
+
+    #[test]
+    fn rays_escaping_the_world_are_black() {
+        let world = World::default();
+        let ray = Ray::new(point(0., 0., -5.), vector(0., 1., 0.));
+        assert_eq!(mean_radiance(&world, &ray, 4), BLACK);
+    }
+
+    #[test]
+    fn direct_light_without_ambient() {
+        let world = World::default();
+        let ray = Ray::new(point(0., 0., -5.), vector(0., 0., 1.));
+        let radiance = PathTracer::new(0).radiance(&world, &ray, &mut rand::thread_rng());
+        let whitted = Whitted::default().color_at(&world, &ray, 0, &mut rand::thread_rng());
+        let ambient = color(0.8, 1.0, 0.6) * 0.1;
+        assert_relative_eq!(radiance, whitted - ambient, epsilon = 1e-5);
+    }
+
+    #[test]
+    fn indirect_light_bleeds_colour() {
+        let world = bleeding_world();
+        // Looking at the wall, which faces away from the light
+        let ray = Ray::new(point(0., 1., 0.), vector(0., 0., 1.));
+        let whitted = Whitted::default().radiance(&world, &ray, &mut rand::thread_rng());
+        let radiance = mean_radiance(&world, &ray, 200);
+        assert!(radiance.r > whitted.r);
+        assert_eq!(radiance.g, 0.);
+
+        // The floor next to the wall gets red light back from it
+        let ray = Ray::new(point(0., 1., 4.), vector(0., -1., 0.));
+        let floor = mean_radiance(&world, &ray, 200);
+        assert!(floor.r > floor.g);
+    }
+
+    #[test]
+    fn emissive_planes_light_through_the_paths() {
+        let floor = Plane::new(
+            Transform::identity(),
+            Material {
+                specular: Mapping::from(0.),
+                ..Material::default()
+            },
+        );
+        let ceiling = Plane::new(
+            translation(0., 2., 0.),
+            Material {
+                emission: Some(Mapping::from(WHITE)),
+                diffuse: Mapping::from(0.),
+                specular: Mapping::from(0.),
+                ..Material::default()
+            },
+        );
+        let world = World::new(vec![Box::new(floor), Box::new(ceiling)], vec![]);
+        assert!(world.lights.is_empty());
+        // Every bounce off the floor ends on the ceiling
+        let ray = Ray::new(point(0., 1., 0.), vector(0., -1., 1.));
+        assert_relative_eq!(mean_radiance(&world, &ray, 8), WHITE * 0.9, epsilon = 1e-5);
+    }
+
+    #[test]
+    fn emissive_shapes_light_the_scene() {
+        let floor = Plane::new(
+            Transform::identity(),
+            Material {
+                specular: Mapping::from(0.),
+                ..Material::default()
+            },
+        );
+        let bulb = Sphere::new(
+            translation(0., 2., 0.) * scaling(0.5, 0.5, 0.5),
+            Material {
+                emission: Some(Mapping::from(WHITE * 4.)),
+                diffuse: Mapping::from(0.),
+                specular: Mapping::from(0.),
+                ..Material::default()
+            },
+        );
+        let world = World::new(vec![Box::new(floor), Box::new(bulb)], vec![]);
+        let at_bulb = Ray::new(point(0., 2., -5.), vector(0., 0., 1.));
+        assert_relative_eq!(mean_radiance(&world, &at_bulb, 4), WHITE * 4.);
+        // The floor under the bulb receives π L r² / d²
+        let at_floor = Ray::new(point(0., 1., -1.), vector(0., -1., 1.));
+        assert_relative_eq!(
+            mean_radiance(&world, &at_floor, 512),
+            WHITE * 0.225,
+            epsilon = 0.02
+        );
+    }
+}
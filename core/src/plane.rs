@@ -33,7 +33,7 @@ impl Shape for Plane {
         &mut self.base
     }
 
-    fn local_intersects(&self, ray: &Ray) -> Option<Intersection> {
+    fn local_intersects(&self, ray: &Ray) -> Option<Intersection<'_>> {
         if f32::abs(ray.direction.y) < f32::EPSILON {
             None
         } else {
@@ -5,12 +5,31 @@ use crate::*;
 pub struct Ray {
     pub origin: Point,
     pub direction: Vector,
+    /// Instant within the camera shutter interval the ray was cast at.
+    pub time: f32,
+    /// Wavelength in micrometres of rays split by dispersion, `None` for
+    /// white light.
+    pub wavelength: Option<f32>,
 }
 
 impl Ray {
     #[inline]
     pub fn new(origin: Point, direction: Vector) -> Ray {
-        Ray { origin, direction }
+        Ray::new_with_time(origin, direction, 0.)
+    }
+
+    #[inline]
+    pub fn new_with_time(origin: Point, direction: Vector, time: f32) -> Ray {
+        Ray {
+            origin,
+            direction,
+            time,
+            wavelength: None,
+        }
+    }
+
+    pub fn with_wavelength(self, wavelength: Option<f32>) -> Ray {
+        Ray { wavelength, ..self }
     }
 
     pub fn position(&self, t: f32) -> Point {
@@ -19,10 +38,12 @@ impl Ray {
 
     #[inline(always)]
     pub fn transform(&self, trans: &Projective3<f32>) -> Ray {
-        Ray::new(
+        Ray::new_with_time(
             trans.transform_point(&self.origin),
             trans.transform_vector(&self.direction),
+            self.time,
         )
+        .with_wavelength(self.wavelength)
     }
 }
 
@@ -71,4 +92,10 @@ mod tests {
         assert_relative_eq!(r2.origin, point(2., 6., 12.));
         assert_relative_eq!(r2.direction, vector(0., 3., 0.));
     }
+
+    #[test]
+    fn transforming_keeps_time() {
+        let r = Ray::new_with_time(point(1., 2., 3.), vector(0., 1., 0.), 0.25);
+        assert_eq!(r.transform(&translation(3., 4., 5.)).time, 0.25);
+    }
 }
@@ -0,0 +1,337 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::*;
+
+pub const DEFAULT_TILE_SIZE: usize = 32;
+
+/// Rectangular block of pixels rendered as a unit by a single thread.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Tile {
+    pub fn len(&self) -> usize {
+        self.width * self.height
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterates over the canvas coordinates covered by the tile, row by row.
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize)> {
+        let Tile {
+            x,
+            y,
+            width,
+            height,
+        } = *self;
+        (y..y + height).flat_map(move |py| (x..x + width).map(move |px| (px, py)))
+    }
+}
+
+/// Splits a `width` x `height` image into tiles of at most `tile_size` x `tile_size` pixels.
+pub fn tiles(width: usize, height: usize, tile_size: usize) -> Vec<Tile> {
+    let tile_size = tile_size.max(1);
+    let mut tiles = vec![];
+    for y in (0..height).step_by(tile_size) {
+        for x in (0..width).step_by(tile_size) {
+            tiles.push(Tile {
+                x,
+                y,
+                width: tile_size.min(width - x),
+                height: tile_size.min(height - y),
+            });
+        }
+    }
+    tiles
+}
+
+/// Snapshot of a render in progress, reported each time a tile is completed.
+#[derive(Debug, Copy, Clone)]
+pub struct RenderProgress {
+    pub tile: Tile,
+    pub tiles_done: usize,
+    pub tiles_total: usize,
+    /// Number of camera rays traced so far.
+    pub rays: u64,
+    pub elapsed: Duration,
+}
+
+impl RenderProgress {
+    pub fn fraction(&self) -> f32 {
+        if self.tiles_total == 0 {
+            1.
+        } else {
+            self.tiles_done as f32 / self.tiles_total as f32
+        }
+    }
+
+    pub fn rays_per_second(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0. {
+            self.rays as f64 / secs
+        } else {
+            0.
+        }
+    }
+
+    /// Estimated remaining time, extrapolated from the average time per tile.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.tiles_done == 0 {
+            return None;
+        }
+        let remaining = (self.tiles_total - self.tiles_done) as f64 / self.tiles_done as f64;
+        Some(self.elapsed.mul_f64(remaining))
+    }
+}
+
+/// Cloneable flag used to ask a running render to stop.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Records which pixels of a canvas have been rendered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderMask {
+    pub width: usize,
+    pub height: usize,
+    done: Vec<bool>,
+}
+
+impl RenderMask {
+    pub fn new(width: usize, height: usize) -> RenderMask {
+        RenderMask {
+            width,
+            height,
+            done: vec![false; width * height],
+        }
+    }
+
+    pub fn set_tile(&mut self, tile: &Tile) {
+        for (x, y) in tile.pixels() {
+            self.done[x + y * self.width] = true;
+        }
+    }
+
+    pub fn is_done(&self, x: usize, y: usize) -> bool {
+        self.done[x + y * self.width]
+    }
+
+    pub fn count_done(&self) -> usize {
+        self.done.iter().filter(|&&d| d).count()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.done.iter().all(|&d| d)
+    }
+}
+
+/// Result of a render that may have been stopped before finishing.
+#[derive(Debug)]
+pub struct PartialRender {
+    pub canvas: Canvas,
+    pub mask: RenderMask,
+    /// Number of samples taken for each pixel, row by row.
+    pub sample_counts: Vec<usize>,
+    /// Buffers of the AOVs requested with `Camera::set_aovs`.
+    pub aovs: Vec<AovBuffer>,
+}
+
+impl PartialRender {
+    pub fn is_complete(&self) -> bool {
+        self.mask.is_complete()
+    }
+
+    /// Debug image of the number of samples per pixel, from blue (fewest) to
+    /// red (most).
+    pub fn sample_heatmap(&self) -> Canvas {
+        let (width, height) = (self.mask.width, self.mask.height);
+        let heatmap = Canvas::new(width, height);
+        let min = self.sample_counts.iter().copied().min().unwrap_or(0);
+        let max = self.sample_counts.iter().copied().max().unwrap_or(0);
+        let range = (max - min).max(1) as f32;
+        for (idx, &n) in self.sample_counts.iter().enumerate() {
+            let t = (n - min) as f32 / range;
+            let c = if t < 0.5 {
+                color(0., 2. * t, 1. - 2. * t)
+            } else {
+                color(2. * t - 1., 2. - 2. * t, 0.)
+            };
+            heatmap.set(idx % width, idx / width, c.into());
+        }
+        heatmap
+    }
+}
+
+/// Render running on a background thread. See `Camera::spawn_render`.
+pub struct RenderHandle {
+    token: CancelToken,
+    thread: JoinHandle<PartialRender>,
+}
+
+impl RenderHandle {
+    pub(crate) fn new(token: CancelToken, thread: JoinHandle<PartialRender>) -> RenderHandle {
+        RenderHandle { token, thread }
+    }
+
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    pub fn token(&self) -> CancelToken {
+        self.token.clone()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Waits for the render to stop, either because it finished, it was
+    /// cancelled or it ran out of time.
+    pub fn join(self) -> PartialRender {
+        self.thread.join().unwrap()
+    }
+}
+
+/// Shared queue of tiles. Idle threads pull the next pending tile, so expensive
+/// regions of the image don't leave the other threads waiting.
+pub(crate) struct TileQueue {
+    tiles: Vec<Tile>,
+    next: AtomicUsize,
+    done: AtomicUsize,
+    rays: AtomicU64,
+    start: Instant,
+}
+
+impl TileQueue {
+    pub(crate) fn new(tiles: Vec<Tile>) -> TileQueue {
+        TileQueue {
+            tiles,
+            next: AtomicUsize::new(0),
+            done: AtomicUsize::new(0),
+            rays: AtomicU64::new(0),
+            start: Instant::now(),
+        }
+    }
+
+    pub(crate) fn next_tile(&self) -> Option<Tile> {
+        let idx = self.next.fetch_add(1, Ordering::Relaxed);
+        self.tiles.get(idx).copied()
+    }
+
+    pub(crate) fn complete(&self, tile: Tile, rays: u64) -> RenderProgress {
+        let rays = self.rays.fetch_add(rays, Ordering::Relaxed) + rays;
+        let tiles_done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        RenderProgress {
+            tile,
+            tiles_done,
+            tiles_total: self.tiles.len(),
+            rays,
+            elapsed: self.start.elapsed(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_cover_image() {
+        let ts = tiles(70, 33, 32);
+        assert_eq!(ts.len(), 6);
+        assert_eq!(
+            ts[2],
+            Tile {
+                x: 64,
+                y: 0,
+                width: 6,
+                height: 32,
+            }
+        );
+        assert_eq!(ts.iter().map(Tile::len).sum::<usize>(), 70 * 33);
+    }
+
+    #[test]
+    fn tile_pixels() {
+        let tile = Tile {
+            x: 2,
+            y: 3,
+            width: 2,
+            height: 2,
+        };
+        let pixels = tile.pixels().collect::<Vec<_>>();
+        assert_eq!(pixels, vec![(2, 3), (3, 3), (2, 4), (3, 4)]);
+    }
+
+    #[test]
+    fn progress_eta() {
+        let progress = RenderProgress {
+            tile: tiles(1, 1, 1)[0],
+            tiles_done: 1,
+            tiles_total: 4,
+            rays: 100,
+            elapsed: Duration::from_secs(2),
+        };
+        assert_relative_eq!(progress.fraction(), 0.25);
+        assert_relative_eq!(progress.rays_per_second(), 50.);
+        assert_eq!(progress.eta(), Some(Duration::from_secs(6)));
+    }
+
+    #[test]
+    fn mask_tracks_tiles() {
+        let mut mask = RenderMask::new(4, 4);
+        mask.set_tile(&tiles(4, 4, 2)[1]);
+        assert!(mask.is_done(2, 1));
+        assert!(!mask.is_done(1, 1));
+        assert_eq!(mask.count_done(), 4);
+        assert!(!mask.is_complete());
+    }
+
+    #[test]
+    fn heatmap_ranges_from_blue_to_red() {
+        let partial = PartialRender {
+            canvas: Canvas::new(2, 1),
+            mask: RenderMask::new(2, 1),
+            sample_counts: vec![4, 64],
+            aovs: vec![],
+        };
+        let heatmap = partial.sample_heatmap();
+        assert_eq!(heatmap.get(0, 0), BLUE.into());
+        assert_eq!(heatmap.get(1, 0), RED.into());
+    }
+
+    #[test]
+    fn queue_hands_out_each_tile_once() {
+        let queue = TileQueue::new(tiles(4, 4, 2));
+        let mut n = 0;
+        while let Some(tile) = queue.next_tile() {
+            let progress = queue.complete(tile, tile.len() as u64);
+            n += 1;
+            assert_eq!(progress.tiles_done, n);
+        }
+        assert_eq!(n, 4);
+        assert_eq!(queue.complete(tiles(1, 1, 1)[0], 0).rays, 16);
+    }
+}
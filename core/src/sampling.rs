@@ -0,0 +1,486 @@
+use rand::Rng;
+
+use crate::*;
+
+use self::Filter::*;
+use self::SamplingStrategy::*;
+
+const GAUSSIAN_ALPHA: f32 = 2.;
+const MITCHELL_B: f32 = 1. / 3.;
+const MITCHELL_C: f32 = 1. / 3.;
+
+/// How sample positions are distributed inside a pixel.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SamplingStrategy {
+    /// Uniform grid. The number of samples is rounded up to a square.
+    Regular,
+    /// Uniform grid with each sample jittered inside its cell. The number of
+    /// samples is rounded up to a square.
+    Stratified,
+    /// Halton sequence in bases 2 and 3.
+    Halton,
+    /// First two dimensions of the Sobol sequence.
+    Sobol,
+}
+
+/// Reconstruction filter used to weight the samples of a pixel.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Filter {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+}
+
+impl Filter {
+    /// Half width of the filter support, in pixels.
+    pub fn radius(&self) -> f32 {
+        match self {
+            Box => 0.5,
+            Tent => 1.,
+            Gaussian => 1.5,
+            Mitchell => 2.,
+        }
+    }
+
+    /// Weight of a sample at offset `(dx, dy)` from the pixel centre.
+    pub fn weight(&self, dx: f32, dy: f32) -> f32 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, d: f32) -> f32 {
+        let r = self.radius();
+        let d = d.abs();
+        if d > r {
+            return 0.;
+        }
+        match self {
+            Box => 1.,
+            Tent => 1. - d / r,
+            Gaussian => (-GAUSSIAN_ALPHA * d * d).exp() - (-GAUSSIAN_ALPHA * r * r).exp(),
+            Mitchell => mitchell(2. * d / r),
+        }
+    }
+}
+
+// Mitchell-Netravali cubic, defined in [0, 2].
+fn mitchell(x: f32) -> f32 {
+    let (b, c) = (MITCHELL_B, MITCHELL_C);
+    let x2 = x * x;
+    let x3 = x2 * x;
+    if x > 1. {
+        ((-b - 6. * c) * x3
+            + (6. * b + 30. * c) * x2
+            + (-12. * b - 48. * c) * x
+            + (8. * b + 24. * c))
+            / 6.
+    } else {
+        ((12. - 9. * b - 6. * c) * x3 + (-18. + 12. * b + 6. * c) * x2 + (6. - 2. * b)) / 6.
+    }
+}
+
+/// Adaptive refinement: after the initial samples, pixels contrasting with a
+/// neighbour or with a noisy estimate get more samples, one batch at a time.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AdaptiveSampling {
+    pub max_samples: usize,
+    /// Maximum luminance difference with the neighbours and maximum standard
+    /// error of the luminance accepted for a pixel.
+    pub threshold: f32,
+}
+
+/// Sampling configuration of a camera.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PixelSampling {
+    pub strategy: SamplingStrategy,
+    pub filter: Filter,
+    pub samples: usize,
+    pub adaptive: Option<AdaptiveSampling>,
+}
+
+impl Default for PixelSampling {
+    fn default() -> PixelSampling {
+        PixelSampling {
+            strategy: Regular,
+            filter: Box,
+            samples: 1,
+            adaptive: None,
+        }
+    }
+}
+
+impl PixelSampling {
+    pub fn new(strategy: SamplingStrategy, filter: Filter, samples: usize) -> PixelSampling {
+        PixelSampling {
+            strategy,
+            filter,
+            samples: samples.max(1),
+            adaptive: None,
+        }
+    }
+
+    /// Number of samples actually taken per pixel.
+    pub fn samples_per_pixel(&self) -> usize {
+        match self.strategy {
+            Regular | Stratified => grid_size(self.samples).pow(2),
+            Halton | Sobol => self.samples.max(1),
+        }
+    }
+
+    /// Sample positions in the unit square for the `batch`-th batch of samples
+    /// of pixel `(x, y)`. Low-discrepancy sequences are shifted by a per-pixel
+    /// offset so that neighbouring pixels don't share the same pattern. Later
+    /// batches of a regular grid are jittered, otherwise they would repeat the
+    /// positions of the first one.
+    pub fn unit_samples<R: Rng>(
+        &self,
+        x: usize,
+        y: usize,
+        batch: usize,
+        rng: &mut R,
+    ) -> Vec<(f32, f32)> {
+        let n = self.samples.max(1);
+        let first = (batch * n) as u32;
+        match self.strategy {
+            Regular if batch == 0 => {
+                let k = grid_size(n);
+                grid(k).map(|(i, j)| cell(k, i, j, 0.5, 0.5)).collect()
+            }
+            Regular | Stratified => {
+                let k = grid_size(n);
+                grid(k)
+                    .map(|(i, j)| cell(k, i, j, rng.gen(), rng.gen()))
+                    .collect()
+            }
+            Halton => {
+                let offset = pixel_offset(x, y);
+                (first..first + n as u32)
+                    .map(|i| rotate((radical_inverse(i, 2), radical_inverse(i, 3)), offset))
+                    .collect()
+            }
+            Sobol => {
+                let offset = pixel_offset(x, y);
+                (first..first + n as u32)
+                    .map(|i| rotate(sobol_2d(i), offset))
+                    .collect()
+            }
+        }
+    }
+
+    /// Sample offsets from the pixel centre, spread over the filter support,
+    /// together with their filter weights.
+    pub fn pixel_samples<R: Rng>(
+        &self,
+        x: usize,
+        y: usize,
+        batch: usize,
+        rng: &mut R,
+    ) -> Vec<(f32, f32, f32)> {
+        let radius = self.filter.radius();
+        self.unit_samples(x, y, batch, rng)
+            .into_iter()
+            .map(|(u, v)| {
+                let dx = (2. * u - 1.) * radius;
+                let dy = (2. * v - 1.) * radius;
+                (dx, dy, self.filter.weight(dx, dy))
+            })
+            .collect()
+    }
+}
+
+// Fraction of the total absolute filter weight below which the weighted
+// mean of a pixel is considered unreliable.
+const MIN_WEIGHT_FRACTION: f32 = 0.5;
+
+/// Running estimate of the colour of a pixel.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PixelEstimate {
+    sum: ColorRgbFloat,
+    weight_sum: f32,
+    abs_weight_sum: f32,
+    color_sum: ColorRgbFloat,
+    luminance_sum: f32,
+    luminance_sq_sum: f32,
+    pub samples: usize,
+}
+
+impl PixelEstimate {
+    pub fn add(&mut self, color: ColorRgbFloat, weight: f32) {
+        let luminance = color.luminance();
+        self.sum = self.sum + color * weight;
+        self.weight_sum += weight;
+        self.abs_weight_sum += weight.abs();
+        self.color_sum = self.color_sum + color;
+        self.luminance_sum += luminance;
+        self.luminance_sq_sum += luminance * luminance;
+        self.samples += 1;
+    }
+
+    /// Filtered colour of the pixel. When negative filter lobes cancel out
+    /// most of the weight, the plain mean of the samples is used instead, and
+    /// channels pushed below zero are clamped.
+    pub fn color(&self) -> ColorRgbFloat {
+        if self.weight_sum > MIN_WEIGHT_FRACTION * self.abs_weight_sum {
+            let c = self.sum * (1. / self.weight_sum);
+            color(c.r.max(0.), c.g.max(0.), c.b.max(0.))
+        } else if self.samples > 0 {
+            self.color_sum * (1. / self.samples as f32)
+        } else {
+            BLACK
+        }
+    }
+
+    pub fn luminance(&self) -> f32 {
+        if self.samples == 0 {
+            0.
+        } else {
+            self.luminance_sum / self.samples as f32
+        }
+    }
+
+    /// Standard error of the mean luminance. Zero until there are two samples.
+    pub fn std_error(&self) -> f32 {
+        if self.samples < 2 {
+            return 0.;
+        }
+        let n = self.samples as f32;
+        let mean = self.luminance_sum / n;
+        let variance = (self.luminance_sq_sum / n - mean * mean).max(0.) * n / (n - 1.);
+        (variance / n).sqrt()
+    }
+}
+
+fn grid_size(n: usize) -> usize {
+    (n.max(1) as f32).sqrt().ceil() as usize
+}
+
+fn grid(k: usize) -> impl Iterator<Item = (usize, usize)> {
+    (0..k).flat_map(move |j| (0..k).map(move |i| (i, j)))
+}
+
+#[inline]
+fn cell(k: usize, i: usize, j: usize, u: f32, v: f32) -> (f32, f32) {
+    ((i as f32 + u) / k as f32, (j as f32 + v) / k as f32)
+}
+
+#[inline]
+fn rotate((u, v): (f32, f32), (du, dv): (f32, f32)) -> (f32, f32) {
+    ((u + du).fract(), (v + dv).fract())
+}
+
+/// Radical inverse of `i` in the given base, in `[0, 1)`.
+pub fn radical_inverse(mut i: u32, base: u32) -> f32 {
+    let inv_base = 1. / base as f32;
+    let mut f = inv_base;
+    let mut result = 0.;
+    while i > 0 {
+        result += (i % base) as f32 * f;
+        i /= base;
+        f *= inv_base;
+    }
+    result
+}
+
+/// `i`-th point of the two dimensional Sobol sequence.
+pub fn sobol_2d(i: u32) -> (f32, f32) {
+    let mut x = 0u32;
+    let mut y = 0u32;
+    let mut v = 1u32 << 31;
+    let mut w = 1u32 << 31;
+    let mut i = i;
+    while i > 0 {
+        if i & 1 == 1 {
+            x ^= v;
+            y ^= w;
+        }
+        i >>= 1;
+        v >>= 1;
+        w ^= w >> 1;
+    }
+    let scale = 1. / (1u64 << 32) as f64;
+    ((x as f64 * scale) as f32, (y as f64 * scale) as f32)
+}
+
+/// Direction in the hemisphere around `normal` for the point `(u, v)` of the
+/// unit square, distributed with a density proportional to the cosine to the
+/// normal.
+pub fn cosine_hemisphere(normal: &UnitVector, u: f32, v: f32) -> UnitVector {
+    let (sin_phi, cos_phi) = (2. * std::f32::consts::PI * u).sin_cos();
+    let r = v.sqrt();
+    let (tangent, bitangent) = orthonormal_basis(normal);
+    let direction = tangent * (r * cos_phi)
+        + bitangent * (r * sin_phi)
+        + normal.into_inner() * (1. - v).max(0.).sqrt();
+    normalize(&direction)
+}
+
+/// Direction around `axis` for the point `(u, v)` of the unit square, spread
+/// over a cosine power lobe that widens with `roughness`, in [0, 1]. A
+/// roughness of 0 gives `axis` itself.
+pub fn glossy_lobe(axis: &UnitVector, roughness: f32, u: f32, v: f32) -> UnitVector {
+    let alpha = roughness * roughness;
+    if alpha <= 0. {
+        return *axis;
+    }
+    // Exponent of the Phong lobe matching a Beckmann distribution of `alpha`
+    let exponent = (2. / (alpha * alpha) - 2.).max(0.);
+    let cos_theta = v.powf(1. / (exponent + 1.));
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let (sin_phi, cos_phi) = (2. * std::f32::consts::PI * u).sin_cos();
+    let (tangent, bitangent) = orthonormal_basis(axis);
+    let direction = tangent * (sin_theta * cos_phi)
+        + bitangent * (sin_theta * sin_phi)
+        + axis.into_inner() * cos_theta;
+    normalize(&direction)
+}
+
+// Two unit vectors perpendicular to `n` and to each other.
+pub(crate) fn orthonormal_basis(n: &UnitVector) -> (Vector, Vector) {
+    let sign = 1f32.copysign(n.z);
+    let a = -1. / (sign + n.z);
+    let b = n.x * n.y * a;
+    (
+        vector(1. + sign * n.x * n.x * a, sign * b, -sign * n.x),
+        vector(b, sign + n.y * n.y * a, -n.y),
+    )
+}
+
+// Deterministic pseudo-random offset for a pixel.
+fn pixel_offset(x: usize, y: usize) -> (f32, f32) {
+    let h = hash((x as u64) << 32 | y as u64);
+    let to_unit = |bits: u64| (bits >> 40) as f32 / (1u64 << 24) as f32;
+    (to_unit(h), to_unit(hash(h)))
+}
+
+fn hash(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::thread_rng;
+
+    use super::*;
+
+    #[test]
+    fn default_sampling_is_pixel_centre() {
+        let samples = PixelSampling::default().pixel_samples(3, 4, 0, &mut thread_rng());
+        assert_eq!(samples, vec![(0., 0., 1.)]);
+    }
+
+    #[test]
+    fn regular_grid_rounds_up_to_square() {
+        let sampling = PixelSampling::new(Regular, Box, 3);
+        assert_eq!(sampling.samples_per_pixel(), 4);
+        let samples = sampling.unit_samples(0, 0, 0, &mut thread_rng());
+        assert_eq!(
+            samples,
+            vec![(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)]
+        );
+    }
+
+    #[test]
+    fn stratified_samples_stay_in_their_cell() {
+        let sampling = PixelSampling::new(Stratified, Box, 4);
+        let samples = sampling.unit_samples(0, 0, 0, &mut thread_rng());
+        let (u, v) = samples[3];
+        assert!((0.5..1.).contains(&u) && (0.5..1.).contains(&v));
+    }
+
+    #[test]
+    fn later_batches_continue_the_sequence() {
+        let sampling = PixelSampling::new(Sobol, Box, 2);
+        let mut rng = thread_rng();
+        let first = sampling.unit_samples(1, 1, 0, &mut rng);
+        let second = sampling.unit_samples(1, 1, 1, &mut rng);
+        assert_eq!(first.len(), 2);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn pixel_estimate() {
+        let mut estimate = PixelEstimate::default();
+        estimate.add(WHITE, 1.);
+        assert_relative_eq!(estimate.std_error(), 0.);
+        estimate.add(BLACK, 3.);
+        assert_relative_eq!(estimate.color(), color(0.25, 0.25, 0.25));
+        assert_relative_eq!(estimate.luminance(), 0.5);
+        assert_relative_eq!(estimate.std_error(), 0.5);
+    }
+
+    #[test]
+    fn pixel_estimate_with_negative_weights() {
+        // Mostly cancelled weights fall back to the plain mean
+        let mut estimate = PixelEstimate::default();
+        estimate.add(WHITE, 1.);
+        estimate.add(WHITE * 3., -0.9);
+        assert_relative_eq!(estimate.color(), WHITE * 2.);
+        let mut estimate = PixelEstimate::default();
+        estimate.add(WHITE, -0.1);
+        assert_relative_eq!(estimate.color(), WHITE);
+        // Small negative lobes don't push channels below zero
+        let mut estimate = PixelEstimate::default();
+        estimate.add(color(0.1, 0.5, 0.5), 1.);
+        estimate.add(color(1., 0., 0.), -0.2);
+        assert_relative_eq!(estimate.color(), color(0., 0.625, 0.625));
+    }
+
+    #[test]
+    fn halton_sequence() {
+        assert_relative_eq!(radical_inverse(1, 2), 0.5);
+        assert_relative_eq!(radical_inverse(3, 2), 0.75);
+        assert_relative_eq!(radical_inverse(1, 3), 1. / 3.);
+        assert_relative_eq!(radical_inverse(5, 3), 7. / 9.);
+    }
+
+    #[test]
+    fn sobol_sequence() {
+        assert_eq!(sobol_2d(0), (0., 0.));
+        assert_eq!(sobol_2d(1), (0.5, 0.5));
+        assert_eq!(sobol_2d(2), (0.25, 0.75));
+        assert_eq!(sobol_2d(3), (0.75, 0.25));
+    }
+
+    #[test]
+    fn filter_weights() {
+        assert_relative_eq!(Box.weight(0.4, -0.4), 1.);
+        assert_relative_eq!(Box.weight(0.6, 0.), 0.);
+        assert_relative_eq!(Tent.weight(0.5, 0.), 0.5);
+        assert_relative_eq!(Gaussian.weight(1.5, 0.), 0.);
+        assert!(Gaussian.weight(0., 0.) > Gaussian.weight(0.5, 0.));
+        assert_relative_eq!(Mitchell.weight(0., 0.), (8. / 9.) * (8. / 9.));
+        assert_relative_eq!(Mitchell.weight(2., 0.), 0., epsilon = 1e-6);
+        assert!(Mitchell.weight(1.5, 0.) < 0.);
+    }
+
+    #[test]
+    fn cosine_hemisphere_directions() {
+        let normals = [vector(0., 0., 1.), vector(0., 0., -1.), vector(1., 2., -3.)];
+        for normal in normals.iter().map(normalize) {
+            let up = cosine_hemisphere(&normal, 0.3, 0.);
+            assert_relative_eq!(up, normal, epsilon = 1e-5);
+            for (u, v) in [(0.1, 0.5), (0.6, 0.9), (0.9, 0.99)] {
+                let direction = cosine_hemisphere(&normal, u, v);
+                assert_relative_eq!(dot(&direction, &normal), (1. - v).sqrt(), epsilon = 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn glossy_lobe_widens_with_roughness() {
+        let axis = normalize(&vector(1., 1., 0.));
+        assert_eq!(glossy_lobe(&axis, 0., 0.3, 0.3), axis);
+        let spread = |roughness| 1. - dot(&glossy_lobe(&axis, roughness, 0.3, 0.3), &axis);
+        assert!(spread(0.1) < 1e-3);
+        assert!(spread(0.1) < spread(0.5));
+        assert!(spread(0.5) < spread(1.));
+        assert_relative_eq!(
+            dot(&glossy_lobe(&axis, 1., 0.3, 1.), &axis),
+            1.,
+            epsilon = 1e-6
+        );
+    }
+}
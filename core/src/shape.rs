@@ -7,16 +7,20 @@ use crate::*;
 #[derive(Debug)]
 pub struct BaseShape {
     transform_inverse: Transform,
+    motion: Option<AnimatedTransform>,
     material: Material,
     parent: AtomicPtr<Group>,
+    id: u32,
 }
 
 impl BaseShape {
     pub fn new(transform: Transform, material: Material) -> BaseShape {
         BaseShape {
             transform_inverse: transform.inverse(),
+            motion: None,
             material,
             parent: AtomicPtr::new(core::ptr::null_mut()),
+            id: 0,
         }
     }
 }
@@ -26,40 +30,65 @@ pub trait Shape: Debug + Sync + Send {
     fn get_bounds(&self) -> Bounds;
     fn get_base(&self) -> &BaseShape;
     fn get_base_mut(&mut self) -> &mut BaseShape;
-    fn local_intersects(&self, local_ray: &Ray) -> Option<Intersection>;
+    fn local_intersects(&self, local_ray: &Ray) -> Option<Intersection<'_>>;
     fn local_normal_at(&self, point: &Point, intersection: &Intersection) -> UnitVector;
 
-    fn intersects(&self, ray: &Ray) -> Option<Intersection> {
-        let local_ray = ray.transform(&self.get_transform_inverse());
+    /// Area of the surface in object space, 0 for shapes that can't be
+    /// sampled.
+    fn local_area(&self) -> f32 {
+        0.
+    }
+
+    /// Point of the surface in object space and its normal, uniformly
+    /// distributed over the area for `u` and `v` uniform in [0, 1).
+    fn local_sample(&self, _u: f32, _v: f32) -> Option<(Point, UnitVector)> {
+        None
+    }
+
+    /// Like `local_sample` at `time`, with the area the sample stands for:
+    /// the inverse of its probability density. Uniform samples stand for the
+    /// whole area, while shapes made of moving or transformed parts weight
+    /// theirs.
+    fn local_sample_at(&self, u: f32, v: f32, _time: f32) -> Option<(Point, UnitVector, f32)> {
+        let (point, normal) = self.local_sample(u, v)?;
+        Some((point, normal, self.local_area()))
+    }
+
+    fn intersects(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        let local_ray = ray.transform(&self.get_transform_inverse_at(ray.time));
         self.local_intersects(&local_ray)
+            .map(|intersection| Intersection {
+                time: ray.time,
+                ..intersection
+            })
     }
 
     fn normal_at(&self, point: &Point, intersection: &Intersection) -> UnitVector {
-        let local_point = self.world_to_object(point);
+        let local_point = self.world_to_object(point, intersection.time);
         let local_normal = self.local_normal_at(&local_point, intersection);
-        self.normal_to_world(&local_normal)
+        self.normal_to_world(&local_normal, intersection.time)
     }
 
-    fn world_to_object(&self, point: &Point) -> Point {
+    fn world_to_object(&self, point: &Point, time: f32) -> Point {
         let parent = self.get_parent();
         let point = match parent {
-            Some(parent) => parent.world_to_object(point),
+            Some(parent) => parent.world_to_object(point, time),
             None => *point,
         };
 
-        let t_inv = self.get_transform_inverse();
+        let t_inv = self.get_transform_inverse_at(time);
         t_inv * point
     }
 
-    fn normal_to_world(&self, local_normal: &Vector) -> UnitVector {
-        let t_inv = self.get_transform_inverse();
+    fn normal_to_world(&self, local_normal: &Vector, time: f32) -> UnitVector {
+        let t_inv = self.get_transform_inverse_at(time);
         let mut world_normal = t_inv.matrix().transpose() * local_normal.to_homogeneous();
         world_normal[3] = 0.;
         let normal = UnitVector::new_normalize(Vector::from_homogeneous(world_normal).unwrap());
 
         let parent = self.get_parent();
         match parent {
-            Some(parent) => parent.normal_to_world(&normal.into_inner()),
+            Some(parent) => parent.normal_to_world(&normal.into_inner(), time),
             None => normal,
         }
     }
@@ -84,6 +113,30 @@ pub trait Shape: Debug + Sync + Send {
         self.get_base().transform_inverse
     }
 
+    /// Makes the shape move: its transform is taken from `motion` instead.
+    fn set_motion(&mut self, motion: AnimatedTransform) {
+        self.get_base_mut().motion = Some(motion);
+    }
+
+    fn get_motion(&self) -> Option<&AnimatedTransform> {
+        self.get_base().motion.as_ref()
+    }
+
+    fn get_transform_inverse_at(&self, time: f32) -> Transform {
+        match self.get_motion() {
+            Some(motion) => motion.inverse_at(time),
+            None => self.get_transform_inverse(),
+        }
+    }
+
+    /// Bounds in the parent's space, covering the whole motion of the shape.
+    fn get_parent_bounds(&self) -> Bounds {
+        match self.get_motion() {
+            Some(motion) => motion.transform_bounds(&self.get_bounds()),
+            None => transform_bounds(&self.get_bounds(), &self.get_transform()),
+        }
+    }
+
     fn get_parent(&self) -> Option<&Group> {
         unsafe { self.get_base().parent.load(Ordering::Relaxed).as_ref() }
     }
@@ -91,4 +144,16 @@ pub trait Shape: Debug + Sync + Send {
     fn set_parent(&mut self, group: &mut Group) {
         self.get_base_mut().parent = AtomicPtr::new(group);
     }
+
+    /// Id of the object in the world, shared by all the shapes of a group.
+    fn get_id(&self) -> u32 {
+        match self.get_parent() {
+            Some(parent) => parent.get_id(),
+            None => self.get_base().id,
+        }
+    }
+
+    fn set_id(&mut self, id: u32) {
+        self.get_base_mut().id = id;
+    }
 }
@@ -1,3 +1,5 @@
+use std::f32::consts::PI;
+
 use crate::*;
 
 #[derive(Debug)]
@@ -32,7 +34,7 @@ impl Shape for Sphere {
         &mut self.base
     }
 
-    fn local_intersects(&self, ray: &Ray) -> Option<Intersection> {
+    fn local_intersects(&self, ray: &Ray) -> Option<Intersection<'_>> {
         let sphere_to_ray = ray.origin - point(0., 0., 0.);
         let a = dot(&ray.direction, &ray.direction);
         let b = 2. * dot(&ray.direction, &sphere_to_ray);
@@ -60,6 +62,18 @@ impl Shape for Sphere {
     fn local_normal_at(&self, local_point: &Point, _intersection: &Intersection) -> UnitVector {
         unit_vector_from_vector(local_point - point(0., 0., 0.))
     }
+
+    fn local_area(&self) -> f32 {
+        4. * PI
+    }
+
+    fn local_sample(&self, u: f32, v: f32) -> Option<(Point, UnitVector)> {
+        let z = 1. - 2. * u;
+        let r = f32::sqrt((1. - z * z).max(0.));
+        let (sin, cos) = (2. * PI * v).sin_cos();
+        let normal = unit_vector(r * cos, r * sin, z);
+        Some((point(normal.x, normal.y, normal.z), normal))
+    }
 }
 
 #[cfg(test)]
@@ -200,8 +214,10 @@ mod tests {
     #[test]
     fn assign_material() {
         let mut s = Sphere::default();
-        let mut m = Material::default();
-        m.ambient = Mapping::from(1.);
+        let m = Material {
+            ambient: Mapping::from(1.),
+            ..Material::default()
+        };
         s.set_material(m);
         // assert_eq!(s.get_material().ambient, 1.);
     }
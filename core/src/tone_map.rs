@@ -0,0 +1,110 @@
+use crate::*;
+
+/// Curve compressing linear radiance into the displayable [0, 1] range.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum ToneMapOperator {
+    /// Values above 1 are clipped.
+    #[default]
+    Linear,
+    /// `x / (1 + x)`
+    Reinhard,
+    /// Reinhard, reaching 1 at `white_point` instead of at infinity.
+    ExtendedReinhard { white_point: f32 },
+    /// Narkowicz's fit of the ACES filmic curve.
+    Aces,
+    /// John Hable's Uncharted 2 filmic curve.
+    Hable,
+}
+
+/// Converts linear radiance to display colours: exposure, then the operator,
+/// then the sRGB transfer function when quantised to bytes.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct ToneMapping {
+    pub operator: ToneMapOperator,
+    /// Exposure compensation in stops. Each EV doubles the radiance.
+    pub exposure: f32,
+}
+
+const HABLE_WHITE: f32 = 11.2;
+const HABLE_EXPOSURE_BIAS: f32 = 2.;
+
+#[inline]
+fn hable_partial(x: f32) -> f32 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+}
+
+impl ToneMapOperator {
+    pub fn map(&self, x: f32) -> f32 {
+        let x = x.max(0.);
+        match *self {
+            ToneMapOperator::Linear => x,
+            ToneMapOperator::Reinhard => x / (1. + x),
+            ToneMapOperator::ExtendedReinhard { white_point } => {
+                x * (1. + x / (white_point * white_point)) / (1. + x)
+            }
+            ToneMapOperator::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+            ToneMapOperator::Hable => {
+                hable_partial(x * HABLE_EXPOSURE_BIAS) / hable_partial(HABLE_WHITE)
+            }
+        }
+    }
+}
+
+impl ToneMapping {
+    pub fn new(operator: ToneMapOperator, exposure: f32) -> ToneMapping {
+        ToneMapping { operator, exposure }
+    }
+
+    /// Display referred linear colour, clamped to [0, 1].
+    pub fn map(&self, c: ColorRgbFloat) -> ColorRgbFloat {
+        let scale = self.exposure.exp2();
+        let map = |x: f32| self.operator.map(x * scale).min(1.);
+        color(map(c.r), map(c.g), map(c.b))
+    }
+
+    /// sRGB encoded bytes of a linear colour.
+    pub fn to_byte(&self, c: ColorRgbFloat) -> ColorRgbByte {
+        self.map(c).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_clamps() {
+        let tm = ToneMapping::default();
+        assert_relative_eq!(tm.map(color(0.5, 2., -1.)), color(0.5, 1., 0.));
+        assert_eq!(tm.to_byte(color(0.5, 2., -1.)), color(0.5, 1., 0.).into());
+    }
+
+    #[test]
+    fn exposure_in_stops() {
+        let tm = ToneMapping::new(ToneMapOperator::Linear, -2.);
+        assert_relative_eq!(tm.map(color(2., 0.4, 8.)), color(0.5, 0.1, 1.));
+    }
+
+    #[test]
+    fn operators_compress_highlights() {
+        let operators = [
+            ToneMapOperator::Reinhard,
+            ToneMapOperator::ExtendedReinhard { white_point: 4. },
+            ToneMapOperator::Aces,
+            ToneMapOperator::Hable,
+        ];
+        for op in operators {
+            assert_relative_eq!(op.map(0.), 0., epsilon = 1e-6);
+            let values = [0.1, 0.5, 1., 2., 3.5];
+            for pair in values.windows(2) {
+                assert!(op.map(pair[0]) < op.map(pair[1]), "{:?}", op);
+            }
+            assert!(op.map(3.5) < 1., "{:?}", op);
+        }
+        assert_relative_eq!(ToneMapOperator::Reinhard.map(1.), 0.5);
+        let extended = ToneMapOperator::ExtendedReinhard { white_point: 4. };
+        assert_relative_eq!(extended.map(4.), 1.);
+        assert_relative_eq!(ToneMapOperator::Hable.map(HABLE_WHITE / 2.), 1.);
+    }
+}
@@ -16,6 +16,9 @@ pub struct Triangle {
     e1: Vector,
     e2: Vector,
     normal: NormalType,
+    // Triangles have no transform of their own, but may still move in the
+    // space of their group
+    motion: Option<Box<AnimatedTransform>>,
 }
 
 impl Triangle {
@@ -38,6 +41,7 @@ impl Triangle {
                 e1,
                 e2,
                 normal,
+                motion: None,
             };
             group.add_shape(Box::new(t));
         }
@@ -67,10 +71,6 @@ impl Shape for Triangle {
         unimplemented!()
     }
 
-    fn local_intersects(&self, _ray: &Ray) -> Option<Intersection> {
-        unimplemented!("Triangles don't need local_intersects")
-    }
-
     fn local_normal_at(&self, _local_point: &Point, hit: &Intersection) -> UnitVector {
         match self.normal {
             NormalType::Uniform(n) => n,
@@ -83,7 +83,26 @@ impl Shape for Triangle {
         }
     }
 
-    fn intersects(&self, ray: &Ray) -> Option<Intersection> {
+    fn local_area(&self) -> f32 {
+        magnitude(&cross(&self.e1, &self.e2)) / 2.
+    }
+
+    fn local_sample(&self, u: f32, v: f32) -> Option<(Point, UnitVector)> {
+        // Folds the unit square onto the triangle
+        let (u, v) = if u + v > 1. { (1. - u, 1. - v) } else { (u, v) };
+        let normal = normalize(&cross(&self.e1, &self.e2));
+        Some((self.p1 + self.e1 * u + self.e2 * v, normal))
+    }
+
+    fn intersects(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        // Still triangles skip the transform of the ray
+        match &self.motion {
+            Some(motion) => self.local_intersects(&ray.transform(&motion.inverse_at(ray.time))),
+            None => self.local_intersects(ray),
+        }
+    }
+
+    fn local_intersects(&self, ray: &Ray) -> Option<Intersection<'_>> {
         let dir_cross_e2 = cross(&ray.direction, &self.e2);
         let det = dot(&self.e1, &dir_cross_e2);
         if f32::abs(det) < f32::EPSILON {
@@ -102,7 +121,10 @@ impl Shape for Triangle {
         }
         let t = f * dot(&self.e2, &origin_cross_e1);
         if t > EPS {
-            Some(Intersection::new_with_uv(t, self, (u, v)))
+            Some(Intersection {
+                time: ray.time,
+                ..Intersection::new_with_uv(t, self, (u, v))
+            })
         } else {
             None
         }
@@ -128,6 +150,14 @@ impl Shape for Triangle {
         Transform::identity()
     }
 
+    fn set_motion(&mut self, motion: AnimatedTransform) {
+        self.motion = Some(Box::new(motion));
+    }
+
+    fn get_motion(&self) -> Option<&AnimatedTransform> {
+        self.motion.as_deref()
+    }
+
     fn get_parent(&self) -> Option<&Group> {
         unsafe { self.parent.load(Ordering::Relaxed).as_ref() }
     }
@@ -136,3 +166,33 @@ impl Shape for Triangle {
         self.parent = AtomicPtr::new(group);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moving_triangle() {
+        let mut t = Triangle {
+            parent: AtomicPtr::new(core::ptr::null_mut()),
+            p1: point(0., 1., 0.),
+            e1: vector(-1., -1., 0.),
+            e2: vector(1., -1., 0.),
+            normal: NormalType::Uniform(UnitVector::new_normalize(vector(0., 0., -1.))),
+            motion: None,
+        };
+        t.set_motion(
+            AnimatedTransform::new(&[(0., Transform::identity()), (1., translation(4., 0., 0.))])
+                .unwrap(),
+        );
+        let ray = |time| Ray::new_with_time(point(4., 0.5, -2.), vector(0., 0., 1.), time);
+        assert!(t.intersects(&ray(0.)).is_none());
+        let hit = t.intersects(&ray(1.)).unwrap();
+        assert_relative_eq!(hit.t, 2.);
+        let normal = t.normal_at(&point(4., 0.5, 0.), &hit);
+        assert_relative_eq!(normal.into_inner(), vector(0., 0., -1.));
+        let (min, max) = t.get_parent_bounds();
+        assert_relative_eq!(min, point(-1., 0., 0.));
+        assert_relative_eq!(max, point(5., 1., 0.));
+    }
+}
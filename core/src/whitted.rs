@@ -0,0 +1,387 @@
+use rand::{Rng, RngCore};
+
+use crate::*;
+
+/// Recursive ray tracer of mirror reflections and refractions, up to
+/// `max_depth` bounces. The ambient term of the materials stands in for the
+/// indirect light, optionally darkened by ambient occlusion. Rough surfaces
+/// seen from the camera are sampled with `glossy_samples` rays.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Whitted {
+    pub max_depth: u8,
+    pub ambient_occlusion: Option<AmbientOcclusion>,
+    pub glossy_samples: usize,
+}
+
+impl Default for Whitted {
+    fn default() -> Whitted {
+        Whitted::new(5)
+    }
+}
+
+impl Whitted {
+    pub fn new(max_depth: u8) -> Whitted {
+        Whitted {
+            max_depth,
+            ambient_occlusion: None,
+            glossy_samples: 8,
+        }
+    }
+
+    pub fn color_at(
+        &self,
+        world: &World,
+        ray: &Ray,
+        remaining: u8,
+        rng: &mut dyn RngCore,
+    ) -> ColorRgbFloat {
+        self.color_in(world, ray, None, remaining, rng)
+    }
+
+    /// Colour seen along `ray`, dimmed by the absorbing `medium` it travels
+    /// through up to the hit.
+    fn color_in(
+        &self,
+        world: &World,
+        ray: &Ray,
+        medium: Option<Absorption>,
+        remaining: u8,
+        rng: &mut dyn RngCore,
+    ) -> ColorRgbFloat {
+        let Some(intersection) = world.intersects(ray) else {
+            return BLACK;
+        };
+        let hit = world.prepare_hit(&intersection, ray, medium);
+        let color = self.shade_hit(world, &hit, remaining, rng);
+        match medium {
+            Some(medium) => color * medium.transmittance(intersection.t * ray.direction.norm()),
+            None => color,
+        }
+    }
+
+    pub fn shade_hit(
+        &self,
+        world: &World,
+        object_hit: &Hit,
+        remaining: u8,
+        rng: &mut dyn RngCore,
+    ) -> ColorRgbFloat {
+        let material = object_hit.intersection.object.get_material();
+        let hm = material.get_hit_material(object_hit);
+        // Every light of the scene adds its share of ambient light, the
+        // emissive shapes don't
+        let scene_lights = world
+            .lights
+            .iter()
+            .filter(|light| !matches!(light, Light::Emissive(_)))
+            .count();
+        let mut ambient = hm.color * hm.ambient * scene_lights as f32;
+        if let Some(ambient_occlusion) = &self.ambient_occlusion {
+            ambient = ambient * ambient_occlusion.visibility(world, object_hit, rng);
+        }
+        let surface = hm.emission + ambient + direct_lighting(&hm, world);
+
+        let reflected = self.reflected_color(world, &hm, remaining, rng);
+        let refracted = self.refracted_color(world, &hm, remaining, rng);
+
+        if material.transparency.is_some() && material.reflective.is_some() {
+            let reflectance = object_hit.schlick();
+            surface + reflected * reflectance + refracted * (1. - reflectance)
+        } else {
+            surface + reflected + refracted
+        }
+    }
+
+    fn reflected_color(
+        &self,
+        world: &World,
+        hm: &HitMaterial,
+        remaining: u8,
+        rng: &mut dyn RngCore,
+    ) -> ColorRgbFloat {
+        let hit = hm.hit;
+        let reflective = &hit.intersection.object.get_material().reflective;
+        let specular = hm.specular_reflectance();
+        if remaining == 0 || (reflective.is_none() && specular == BLACK) {
+            return BLACK;
+        }
+
+        let reflectance = reflective.as_ref().map_or(specular, |reflective| {
+            specular + WHITE * reflective.map_at_object(&hit.object_point)
+        });
+        let reflectv = hit.reflectv.into_inner();
+        let wavelength = hit.wavelength;
+        self.trace_lobe(world, hm, hit.point, reflectv, wavelength, remaining, rng) * reflectance
+    }
+
+    fn refracted_color(
+        &self,
+        world: &World,
+        hm: &HitMaterial,
+        remaining: u8,
+        rng: &mut dyn RngCore,
+    ) -> ColorRgbFloat {
+        if remaining == 0 {
+            return BLACK;
+        }
+
+        let hit = hm.hit;
+        let Some(transparency) = &hit.intersection.object.get_material().transparency else {
+            return BLACK;
+        };
+        let transparency = transparency.map_at_object(&hit.object_point);
+        let origin = hit.point - (hit.normalv.into_inner() * EPS);
+        let mut trace = |direction, wavelength| {
+            self.trace_lobe(world, hm, origin, direction, wavelength, remaining, rng) * transparency
+        };
+        match hit.channel_indices {
+            // Each channel refracts at its own angle, and keeps its wavelength
+            Some(indices) => CHANNELS
+                .iter()
+                .zip(indices)
+                .filter_map(|(&(wavelength, mask), (n1, n2))| {
+                    let direction = hit.refraction(n1, n2)?;
+                    Some(trace(direction, Some(wavelength)) * mask)
+                })
+                .sum(),
+            // Nothing is refracted on total internal reflection
+            None => hit
+                .refracted_direction()
+                .map_or(BLACK, |direction| trace(direction, hit.wavelength)),
+        }
+    }
+
+    /// Average colour seen along rays leaving `origin` around `direction`,
+    /// spread by the roughness of the hit. Rays that cross to the other side
+    /// of the surface are dropped. Deeper than the camera hits a single ray
+    /// is traced.
+    #[allow(clippy::too_many_arguments)]
+    fn trace_lobe(
+        &self,
+        world: &World,
+        hm: &HitMaterial,
+        origin: Point,
+        direction: Vector,
+        wavelength: Option<f32>,
+        remaining: u8,
+        rng: &mut dyn RngCore,
+    ) -> ColorRgbFloat {
+        let time = hm.hit.intersection.time;
+        let medium = hm.hit.medium_towards(&direction);
+        let trace = |direction: Vector, rng: &mut dyn RngCore| {
+            let ray = Ray::new_with_time(origin + direction * EPS * 100., direction, time)
+                .with_wavelength(wavelength);
+            self.color_in(world, &ray, medium, remaining - 1, rng)
+        };
+        if hm.roughness <= 0. {
+            return trace(direction, rng);
+        }
+
+        let axis = normalize(&direction);
+        let side = dot(&axis, &hm.hit.normalv);
+        let samples = if remaining == self.max_depth {
+            self.glossy_samples.max(1)
+        } else {
+            1
+        };
+        let mut sum = BLACK;
+        for _ in 0..samples {
+            let spread = glossy_lobe(&axis, hm.roughness, rng.gen(), rng.gen());
+            if dot(&spread, &hm.hit.normalv) * side > 0. {
+                sum = sum + trace(spread.into_inner(), rng);
+            }
+        }
+        sum * (1. / samples as f32)
+    }
+}
+
+impl Integrator for Whitted {
+    fn radiance(&self, world: &World, ray: &Ray, rng: &mut dyn RngCore) -> ColorRgbFloat {
+        self.color_at(world, ray, self.max_depth, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::f32::consts::PI;
+
+    use super::*;
+
+    #[test]
+    fn shade_intersection() {
+        let world = World::default();
+        let ray = Ray::new(point(0., 0., -5.), vector(0., 0., 1.));
+        let xs = world.intersects(&ray).unwrap();
+        let hit = xs.prepare_hit(&ray);
+        let c = Whitted::default().shade_hit(&world, &hit, 0, &mut rand::thread_rng());
+        assert_relative_eq!(c, color(0.38066125, 0.4758265, 0.28549594));
+    }
+
+    #[test]
+    fn shade_intersection_inside() {
+        let mut world = World::default();
+        world.lights = vec![Light::Point(PointLight::new(
+            point(0., 0.25, 0.),
+            color(1., 1., 1.),
+        ))];
+        let ray = Ray::new(point(0., 0., 0.), vector(0., 0., 1.));
+        let intersection = Intersection::new(0.5, &(*world.bounded_shapes[1].shape));
+        let hit = intersection.prepare_hit(&ray);
+        let c = Whitted::default().shade_hit(&world, &hit, 0, &mut rand::thread_rng());
+        assert_relative_eq!(c, color(0.9049845, 0.9049845, 0.9049845));
+    }
+
+    #[test]
+    fn color_at_intersection() {
+        let world = World::default();
+        let ray = Ray::new(point(0., 0., -5.), vector(0., 0., 1.));
+        let c = Whitted::default().color_at(&world, &ray, 0, &mut rand::thread_rng());
+        assert_relative_eq!(c, color(0.38066125, 0.4758265, 0.28549594));
+    }
+
+    #[test]
+    fn color_at_behind() {
+        let mut world = World::default();
+        let material = Material {
+            ambient: Mapping::from(1.),
+            diffuse: Mapping::from(0.),
+            specular: Mapping::from(0.),
+            ..Material::default()
+        };
+        world.bounded_shapes[1].shape.set_material(material);
+        let ray = Ray::new(point(0., 0., -0.75), vector(0., 0., 1.));
+        let c = Whitted::default().color_at(&world, &ray, 0, &mut rand::thread_rng());
+        assert_relative_eq!(
+            c,
+            world.bounded_shapes[1]
+                .shape
+                .get_material()
+                .color
+                .map_at_object(&point(0., 0., 0.))
+        );
+    }
+
+    #[test]
+    fn emissive_shapes_add_no_ambient_light() {
+        let ceiling = Plane::new(
+            translation(0., 2., 0.),
+            Material {
+                ambient: Mapping::from(1.),
+                diffuse: Mapping::from(0.),
+                specular: Mapping::from(0.),
+                ..Material::default()
+            },
+        );
+        let lamp = Sphere::new(
+            translation(0., 10., 0.),
+            Material {
+                emission: Some(Mapping::from(WHITE)),
+                ..Material::default()
+            },
+        );
+        let light = Light::Point(PointLight::new(point(0., 1., 0.), BLACK));
+        let world = World::new(vec![Box::new(ceiling), Box::new(lamp)], vec![light]);
+        assert_eq!(world.lights.len(), 2);
+        let ray = Ray::new(point(0., 0., 0.), vector(0., 1., 0.));
+        let c = Whitted::default().radiance(&world, &ray, &mut rand::thread_rng());
+        assert_relative_eq!(c, WHITE);
+    }
+
+    #[test]
+    fn emissive_shapes_add_no_highlights() {
+        let floor = Plane::new(
+            Transform::identity(),
+            Material {
+                ambient: Mapping::from(0.),
+                diffuse: Mapping::from(0.),
+                specular: Mapping::from(1.),
+                ..Material::default()
+            },
+        );
+        let lamp = Sphere::new(
+            translation(0., 10., 0.),
+            Material {
+                emission: Some(Mapping::from(WHITE)),
+                ..Material::default()
+            },
+        );
+        let world = World::new(vec![Box::new(floor), Box::new(lamp)], vec![]);
+        assert_eq!(world.lights.len(), 1);
+        let ray = Ray::new(point(0., 5., 0.), vector(0., -1., 0.));
+        let c = Whitted::default().radiance(&world, &ray, &mut rand::thread_rng());
+        assert_relative_eq!(c, BLACK);
+    }
+
+    // A mirror floor under a ceiling lit only by its ambient term
+    fn mirror_under_ceiling(roughness: f32) -> World {
+        let mirror = Plane::new(
+            Transform::identity(),
+            Material {
+                ambient: Mapping::from(0.),
+                diffuse: Mapping::from(0.),
+                specular: Mapping::from(0.),
+                reflective: Some(Mapping::from(1.)),
+                roughness: Mapping::from(roughness),
+                ..Material::default()
+            },
+        );
+        let ceiling = Plane::new(
+            translation(0., 2., 0.),
+            Material {
+                ambient: Mapping::from(1.),
+                ..Material::default()
+            },
+        );
+        let light = Light::Point(PointLight::new(point(0., 1., 0.), BLACK));
+        World::new(vec![Box::new(mirror), Box::new(ceiling)], vec![light])
+    }
+
+    #[test]
+    fn rough_mirrors_spread_reflections() {
+        let whitted = Whitted {
+            glossy_samples: 64,
+            ..Whitted::default()
+        };
+        let mut rng = rand::thread_rng();
+        // At a grazing angle, part of the rough lobe goes under the floor
+        let ray = Ray::new(point(0., 1., 0.), vector(0., -1., 4.));
+        let smooth = whitted.radiance(&mirror_under_ceiling(0.), &ray, &mut rng);
+        assert_relative_eq!(smooth, WHITE);
+        let rough = whitted.radiance(&mirror_under_ceiling(1.), &ray, &mut rng);
+        assert!(rough.r > 0.2 && rough.r < 0.95);
+    }
+
+    // A slab of tinted glass of the given thickness in front of a white wall
+    fn glass_slab(thickness: f32) -> World {
+        let glass = Cube::new(
+            scaling(2., 2., thickness / 2.),
+            Material {
+                ambient: Mapping::from(0.),
+                diffuse: Mapping::from(0.),
+                specular: Mapping::from(0.),
+                transparency: Some(Mapping::from(1.)),
+                absorption: Some(Absorption::new(color(1., 0.5, 0.5), 1.)),
+                ..Material::default()
+            },
+        );
+        let wall = Plane::new(
+            translation(0., 0., 5.) * rotation_x(PI / 2.),
+            Material {
+                ambient: Mapping::from(1.),
+                ..Material::default()
+            },
+        );
+        let light = Light::Point(PointLight::new(point(0., 0., -5.), BLACK));
+        World::new(vec![Box::new(glass), Box::new(wall)], vec![light])
+    }
+
+    #[test]
+    fn thick_glass_absorbs_more() {
+        let ray = Ray::new(point(0., 0., -5.), vector(0., 0., 1.));
+        let mut rng = rand::thread_rng();
+        let thin = Whitted::default().radiance(&glass_slab(1.), &ray, &mut rng);
+        assert_relative_eq!(thin, color(1., 0.5, 0.5), epsilon = 1e-2);
+        let thick = Whitted::default().radiance(&glass_slab(2.), &ray, &mut rng);
+        assert_relative_eq!(thick, color(1., 0.25, 0.25), epsilon = 1e-2);
+    }
+}
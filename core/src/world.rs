@@ -2,6 +2,9 @@ use bvh::bvh::BVH;
 
 use crate::*;
 
+// Points sampled on emissive shapes to light a hit.
+const EMISSIVE_SAMPLES: usize = 16;
+
 pub struct World {
     pub bounded_shapes: Vec<BoundedShape>,
     pub lights: Vec<Light>,
@@ -9,16 +12,25 @@ pub struct World {
 }
 
 impl World {
-    pub fn new(shapes: Vec<Box<dyn Shape + Send>>, lights: Vec<Light>) -> World {
+    /// The emissive shapes are added to the `lights`.
+    pub fn new(shapes: Vec<Box<dyn Shape + Send>>, mut lights: Vec<Light>) -> World {
         let mut bounded_shapes = shapes
             .into_iter()
-            .map(|mut s| {
+            .enumerate()
+            .map(|(i, mut s)| {
+                s.set_id(i as u32 + 1);
                 s.shape_added();
                 BoundedShape::new(s)
             })
             .collect::<Vec<_>>();
 
         let bvh = BVH::build(&mut bounded_shapes);
+        for (index, bs) in bounded_shapes.iter().enumerate() {
+            let shape = bs.get_shape();
+            if shape.get_material().emission.is_some() && shape.local_area() > 0. {
+                lights.push(Light::Emissive(EmissiveLight::new(index, EMISSIVE_SAMPLES)));
+            }
+        }
         World {
             bounded_shapes,
             lights,
@@ -26,12 +38,28 @@ impl World {
         }
     }
 
+    /// Whether one of the lights samples the emission of `shape`. The parts
+    /// of a group without an area, like cylinders, are left out of its
+    /// samples.
+    pub fn is_sampled_emitter(&self, shape: &dyn Shape) -> bool {
+        if shape.local_area() <= 0. {
+            return false;
+        }
+        let id = shape.get_id();
+        self.lights.iter().any(|light| match light {
+            Light::Emissive(emissive) => {
+                self.bounded_shapes[emissive.shape].get_shape().get_id() == id
+            }
+            _ => false,
+        })
+    }
+
     fn ray_in_shadow<'a>(
         &'a self,
         ray: &Ray,
         light_distance: f32,
-        cached_shape: Option<&'a (dyn Shape)>,
-    ) -> Option<Intersection> {
+        cached_shape: Option<&'a dyn Shape>,
+    ) -> Option<Intersection<'a>> {
         let it = cached_shape.into_iter();
         let it2 = bvh_intersects(&self.bvh, &self.bounded_shapes, ray)
             .map(|s| s.get_shape() as &dyn Shape);
@@ -40,105 +68,123 @@ impl World {
             .find(|x| x.t < light_distance)
     }
 
-    fn intersects(&self, ray: &Ray) -> Option<Intersection> {
+    /// Closest intersection of `ray` with the shapes of the world.
+    pub fn intersects(&self, ray: &Ray) -> Option<Intersection<'_>> {
         bvh_intersects(&self.bvh, &self.bounded_shapes, ray)
             .filter_map(|s| s.get_shape().intersects(ray))
             .min_by(|min, x| f32::partial_cmp(&min.t, &x.t).unwrap())
     }
 
+    /// Whether a shape blocks `ray` closer than `max_distance` to its origin.
+    pub fn is_blocked(&self, ray: &Ray, max_distance: f32) -> bool {
+        self.ray_in_shadow(ray, max_distance, None).is_some()
+    }
+
     pub fn is_shadowed<'a>(
         &'a self,
         light_hit: &LightHit,
-        cached_shape: Option<&'a (dyn Shape)>,
-    ) -> Option<&(dyn Shape)> {
+        cached_shape: Option<&'a dyn Shape>,
+    ) -> Option<&'a dyn Shape> {
         let LightHit {
             lightv,
             distance,
             point,
+            time,
             ..
         } = light_hit;
-        let r = Ray::new(
+        let r = Ray::new_with_time(
             *point + lightv.into_inner() * 100. * EPS,
             lightv.into_inner(),
+            *time,
         );
         self.ray_in_shadow(&r, *distance, cached_shape)
             .map(|i| i.object)
     }
 
-    fn shade_hit(&self, object_hit: &Hit, remaining: u8) -> ColorRgbFloat {
-        let surface: ColorRgbFloat = self
-            .lights
-            .iter()
-            .map(|light| light.lighting(object_hit, self))
-            .sum();
-
-        let reflected = self.reflected_color(object_hit, remaining);
-        let refracted = self.refracted_color(object_hit, remaining);
-
-        let material = object_hit.intersection.object.get_material();
-        if material.transparency.is_some() && material.reflective.is_some() {
-            let reflectance = object_hit.schlick();
-            surface + reflected * reflectance + refracted * (1. - reflectance)
-        } else {
-            surface + reflected + refracted
+    /// Transparent shapes enclosing `ray` just before `intersection`,
+    /// innermost last, and the other transparent surfaces crossed along with
+    /// it. Every crossing of the line behind the hit is gathered and replayed
+    /// from outside of the world, so rays starting inside a medium are
+    /// handled. The shapes of a group count as a single container.
+    pub fn containers<'a>(
+        &'a self,
+        ray: &Ray,
+        intersection: &Intersection,
+    ) -> (Vec<&'a dyn Shape>, Vec<&'a dyn Shape>) {
+        // Surfaces closer to the hit than the offset of the next ray are
+        // skipped by it, so they are crossed together with the hit
+        let offset = EPS * 100.;
+        let direction = -normalize(&ray.direction).into_inner();
+        let origin = ray.position(intersection.t) - direction * offset;
+        let back = Ray::new_with_time(origin, direction, ray.time);
+        let mut crossings = vec![];
+        for bounded_shape in bvh_intersects(&self.bvh, &self.bounded_shapes, &back) {
+            let shape = bounded_shape.get_shape();
+            let mut distance = 0.;
+            let restart =
+                |distance| Ray::new_with_time(back.position(distance), direction, ray.time);
+            while let Some(x) = shape.intersects(&restart(distance)) {
+                distance += x.t;
+                if x.object.get_material().transparency.is_some() {
+                    crossings.push((distance, x.object));
+                }
+                distance += offset;
+            }
         }
-    }
+        crossings.sort_by(|a, b| b.0.total_cmp(&a.0));
 
-    pub fn color_at(&self, ray: &Ray, remaining: u8) -> ColorRgbFloat {
-        let hit = self.intersects(ray);
-        match hit {
-            Some(h) => self.shade_hit(&h.prepare_hit(ray), remaining),
-            None => BLACK,
+        let behind = crossings.partition_point(|&(distance, _)| distance >= 2. * offset);
+        let mut containers = vec![];
+        for &(_, shape) in &crossings[..behind] {
+            toggle_container(&mut containers, shape);
+        }
+        let mut coincident = crossings[behind..]
+            .iter()
+            .map(|&(_, shape)| shape)
+            .collect::<Vec<_>>();
+        let id = intersection.object.get_id();
+        if let Some(i) = coincident.iter().position(|s| s.get_id() == id) {
+            coincident.remove(i);
         }
+        (containers, coincident)
     }
 
-    fn reflected_color(&self, hit: &Hit, remaining: u8) -> ColorRgbFloat {
-        if remaining == 0 {
-            BLACK
+    /// Hit data of `intersection` for a ray travelling through the absorbing
+    /// `medium`. Transparent objects take their refractive indices and media
+    /// from their containers, while opaque ones don't separate media.
+    pub fn prepare_hit<'a>(
+        &self,
+        intersection: &'a Intersection<'a>,
+        ray: &Ray,
+        medium: Option<Absorption>,
+    ) -> Hit<'a> {
+        if intersection.object.get_material().transparency.is_some() {
+            let (containers, coincident) = self.containers(ray, intersection);
+            intersection.prepare_hit_in(ray, &containers, &coincident)
         } else {
-            let object = hit.intersection.object;
-            match &object.get_material().reflective {
-                Some(reflective) => {
-                    let reflectv = hit.reflectv.into_inner();
-                    let reflect_ray = Ray::new(hit.point + reflectv * EPS * 100., reflectv);
-                    self.color_at(&reflect_ray, remaining - 1)
-                        * reflective.map_at_object(&hit.object_point)
-                }
-                None => BLACK,
+            Hit {
+                absorption1: medium,
+                absorption2: medium,
+                ..intersection.prepare_hit(ray)
             }
         }
     }
 
-    fn refracted_color(&self, hit: &Hit, remaining: u8) -> ColorRgbFloat {
-        if remaining == 0 {
-            return BLACK;
-        }
-
-        let object = hit.intersection.object;
-        let transparency = object.get_material().transparency.as_ref();
-
-        match transparency {
-            Some(transparency) => {
-                let n_ratio = hit.n1 / hit.n2;
-                let cos_i = dot(&hit.eyev, &hit.normalv);
-                let sin2_t = n_ratio * n_ratio * (1. - cos_i * cos_i);
-                if sin2_t > 1. {
-                    // Internal reflection
-                    BLACK
-                } else {
-                    let cos_t = f32::sqrt(1. - sin2_t);
-                    let normal = hit.normalv.into_inner();
-                    let direction =
-                        normal * (n_ratio * cos_i - cos_t) - hit.eyev.into_inner() * n_ratio;
-                    let origin = hit.point - (normal * EPS);
-                    let refract_ray = Ray::new(origin + direction * EPS * 100., direction);
-
-                    self.color_at(&refract_ray, remaining - 1)
-                        * transparency.map_at_object(&hit.object_point)
-                }
-            }
-            None => BLACK,
-        }
+    /// Surface data of the first hit of `ray`, before any lighting. The depth
+    /// is the distance along the ray.
+    pub fn surface_at(&self, ray: &Ray) -> Option<SurfaceSample> {
+        let intersection = self.intersects(ray)?;
+        let hit = intersection.prepare_hit(ray);
+        let object = intersection.object;
+        let material = object.get_material();
+        Some(SurfaceSample {
+            depth: intersection.t * ray.direction.norm(),
+            normal: hit.normalv,
+            albedo: material.color.map_at_object(&hit.object_point),
+            object_id: object.get_id(),
+            material_id: material.id,
+            uv: intersection.uv,
+        })
     }
 }
 
@@ -174,58 +220,167 @@ mod tests {
     }
 
     #[test]
-    fn shade_intersection() {
+    fn intersect_with_negative_zero_direction() {
         let world = World::default();
-        let ray = Ray::new(point(0., 0., -5.), vector(0., 0., 1.));
-        let xs = world.intersects(&ray).unwrap();
-        let hit = xs.prepare_hit(&ray);
-        let c = world.shade_hit(&hit, 0);
-        assert_relative_eq!(c, color(0.38066125, 0.4758265, 0.28549594));
+        let ray = Ray::new(point(0., 0., 5.), -vector(0., 0., 1.));
+        assert_relative_eq!(world.intersects(&ray).unwrap().t, 4.);
     }
 
     #[test]
-    fn shade_intersection_inside() {
-        let world = World {
-            lights: vec![Light::Point(PointLight::new(
-                point(0., 0.25, 0.),
-                color(1., 1., 1.),
-            ))],
-            ..World::default()
+    fn moving_shape_hit_at_ray_time() {
+        let mut sphere = Sphere::new(Transform::identity(), Material::default());
+        let motion =
+            AnimatedTransform::new(&[(0., Transform::identity()), (1., translation(4., 0., 0.))]);
+        sphere.set_motion(motion.unwrap());
+        let world = World::new(vec![Box::new(sphere)], vec![]);
+        let direction = vector(0., 0., 1.);
+        let ray_at = |x, time| Ray::new_with_time(point(x, 0., -5.), direction, time);
+        assert!(world.intersects(&ray_at(0., 0.)).is_some());
+        assert!(world.intersects(&ray_at(4., 0.)).is_none());
+        // The world bounds cover the whole motion
+        let end = ray_at(4., 1.);
+        let intersection = world.intersects(&end).unwrap();
+        assert_relative_eq!(intersection.t, 4.);
+        assert_eq!(intersection.time, 1.);
+        let hit = intersection.prepare_hit(&end);
+        assert_relative_eq!(hit.normalv.into_inner(), vector(0., 0., -1.));
+        assert_relative_eq!(hit.object_point, point(0., 0., -1.));
+    }
+
+    fn glass_sphere(transform: Transform, refractive_index: f32) -> Box<dyn Shape + Send> {
+        let material = Material {
+            transparency: Some(Mapping::from(1.)),
+            refractive_index,
+            ..Material::default()
         };
-        let ray = Ray::new(point(0., 0., 0.), vector(0., 0., 1.));
-        let intersection = Intersection::new(0.5, &(*world.bounded_shapes[1].shape));
-        let hit = intersection.prepare_hit(&ray);
-        let c = world.shade_hit(&hit, 0);
-        assert_relative_eq!(c, color(0.9049845, 0.9049845, 0.9049845));
+        Box::new(Sphere::new(transform, material))
     }
 
     #[test]
-    fn color_at_intersection() {
-        let world = World::default();
-        let ray = Ray::new(point(0., 0., -5.), vector(0., 0., 1.));
-        let c = world.color_at(&ray, 0);
-        assert_relative_eq!(c, color(0.38066125, 0.4758265, 0.28549594));
+    fn refractive_indices_of_nested_media() {
+        let world = World::new(
+            vec![
+                glass_sphere(scaling(2., 2., 2.), 1.5),
+                glass_sphere(translation(0., 0., -0.25), 2.),
+                glass_sphere(translation(0., 0., 0.25), 2.5),
+            ],
+            vec![],
+        );
+        let expected = [
+            (1., 1.5),
+            (1.5, 2.),
+            (2., 2.5),
+            (2.5, 2.5),
+            (2.5, 1.5),
+            (1.5, 1.),
+        ];
+        let direction = vector(0., 0., 1.);
+        let mut ray = Ray::new(point(0., 0., -4.), direction);
+        for (n1, n2) in expected {
+            let intersection = world.intersects(&ray).unwrap();
+            let hit = world.prepare_hit(&intersection, &ray, None);
+            assert_relative_eq!(hit.n1, n1);
+            assert_relative_eq!(hit.n2, n2);
+            ray = Ray::new(hit.point + direction * EPS * 100., direction);
+        }
+        assert!(world.intersects(&ray).is_none());
     }
 
     #[test]
-    fn color_at_behind() {
-        let mut world = World::default();
-        let material = Material {
-            ambient: Mapping::from(1.),
-            diffuse: Mapping::from(0.),
-            specular: Mapping::from(0.),
+    fn air_bubble_in_water_seen_from_the_water() {
+        let world = World::new(
+            vec![
+                glass_sphere(scaling(3., 3., 3.), 1.33),
+                glass_sphere(Transform::identity(), 1.),
+            ],
+            vec![],
+        );
+        // The ray starts inside the water
+        let ray = Ray::new(point(0., 0., -2.), vector(0., 0., 1.));
+        let intersection = world.intersects(&ray).unwrap();
+        let (containers, coincident) = world.containers(&ray, &intersection);
+        assert_eq!(containers.len(), 1);
+        assert!(coincident.is_empty());
+        let hit = world.prepare_hit(&intersection, &ray, None);
+        assert_relative_eq!(hit.n1, 1.33);
+        assert_relative_eq!(hit.n2, 1.);
+        // Without the containers, the bubble would be surrounded by vacuum
+        assert_relative_eq!(intersection.prepare_hit(&ray).n1, 1.);
+    }
+
+    #[test]
+    fn coincident_surfaces_are_crossed_together() {
+        let transparent = |refractive_index| Material {
+            transparency: Some(Mapping::from(1.)),
+            refractive_index,
             ..Material::default()
         };
-        world.bounded_shapes[1].shape.set_material(material);
-        let ray = Ray::new(point(0., 0., -0.75), vector(0., 0., 1.));
-        let c = world.color_at(&ray, 0);
-        assert_relative_eq!(
-            c,
-            world.bounded_shapes[1]
-                .shape
-                .get_material()
-                .color
-                .map_at_object(&point(0., 0., 0.))
+        // Liquid filling the back half of a glass block, sharing its back face
+        let glass = Cube::new(scaling(2., 2., 2.), transparent(1.5));
+        let liquid = Cube::new(
+            translation(0., 0., 1.) * scaling(2., 2., 1.),
+            transparent(1.33),
         );
+        let world = World::new(vec![Box::new(glass), Box::new(liquid)], vec![]);
+        let direction = vector(0., 0., 1.);
+        let mut ray = Ray::new(point(0., 0., -4.), direction);
+        for (n1, n2) in [(1., 1.5), (1.5, 1.33), (1.33, 1.)] {
+            let intersection = world.intersects(&ray).unwrap();
+            let hit = world.prepare_hit(&intersection, &ray, None);
+            assert_relative_eq!(hit.n1, n1);
+            assert_relative_eq!(hit.n2, n2);
+            ray = Ray::new(hit.point + direction * EPS * 100., direction);
+        }
+        assert!(world.intersects(&ray).is_none());
+    }
+
+    #[test]
+    fn sampled_emitters() {
+        let emissive = Material {
+            emission: Some(Mapping::from(WHITE)),
+            ..Material::default()
+        };
+        let mut group = Box::new(Group::new(Transform::identity(), emissive));
+        group.add_shape(Box::new(Sphere::default()));
+        let cylinder = Cylinder::new(translation(5., 0., 0.), Material::default(), true);
+        group.add_shape(Box::new(cylinder));
+        let world = World::new(vec![group], vec![]);
+        let hit_object = |x| {
+            let ray = Ray::new(point(x, 0., -5.), vector(0., 0., 1.));
+            world.intersects(&ray).unwrap().object
+        };
+        assert!(world.is_sampled_emitter(hit_object(0.)));
+        // The cylinder can't be sampled, so its hits count
+        assert!(!world.is_sampled_emitter(hit_object(5.)));
+    }
+
+    #[test]
+    fn dispersion_splits_white_rays() {
+        let prism = Sphere::new(
+            Transform::identity(),
+            Material {
+                transparency: Some(Mapping::from(1.)),
+                refractive_index: 1.5,
+                dispersion: Some(Dispersion::Abbe(20.)),
+                ..Material::default()
+            },
+        );
+        let world = World::new(vec![Box::new(prism)], vec![]);
+        let ray = Ray::new(point(0.5, 0., -5.), vector(0., 0., 1.));
+        let intersection = world.intersects(&ray).unwrap();
+        let hit = world.prepare_hit(&intersection, &ray, None);
+        let [red, green, blue] = hit.channel_indices.unwrap();
+        assert_relative_eq!(green.1, 1.5);
+        assert!(red.1 < green.1 && green.1 < blue.1);
+        // Blue bends more towards the normal
+        let bend = |(n1, n2)| dot(&normalize(&hit.refraction(n1, n2).unwrap()), &hit.normalv);
+        assert!(bend(blue) < bend(red));
+
+        // Rays already split keep their own index
+        let blue_ray = Ray::new(point(0.5, 0., -5.), vector(0., 0., 1.));
+        let blue_ray = blue_ray.with_wavelength(Some(CHANNELS[2].0));
+        let hit = world.prepare_hit(&intersection, &blue_ray, None);
+        assert!(hit.channel_indices.is_none());
+        assert_relative_eq!(hit.n2, blue.1);
     }
 }
@@ -0,0 +1,161 @@
+use crate::*;
+
+/// Values that can be blended between keyframes.
+pub trait Lerp: Copy {
+    fn lerp(self, other: Self, t: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(self, other: f32, t: f32) -> f32 {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for Point {
+    fn lerp(self, other: Point, t: f32) -> Point {
+        Point(
+            self.0.lerp(other.0, t),
+            self.1.lerp(other.1, t),
+            self.2.lerp(other.2, t),
+        )
+    }
+}
+
+impl Lerp for Vector {
+    fn lerp(self, other: Vector, t: f32) -> Vector {
+        Vector(
+            self.0.lerp(other.0, t),
+            self.1.lerp(other.1, t),
+            self.2.lerp(other.2, t),
+        )
+    }
+}
+
+impl Lerp for Rgb {
+    fn lerp(self, other: Rgb, t: f32) -> Rgb {
+        Rgb(
+            self.0.lerp(other.0, t),
+            self.1.lerp(other.1, t),
+            self.2.lerp(other.2, t),
+        )
+    }
+}
+
+impl Easing {
+    /// Maps the linear progress `t` between two keys, in [0, 1].
+    pub fn ease(&self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::Step => 0.,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1. - (1. - t).powi(3),
+            Easing::EaseInOut if t < 0.5 => 4. * t * t * t,
+            Easing::EaseInOut => 1. - (2. - 2. * t).powi(3) / 2.,
+        }
+    }
+}
+
+/// Keys surrounding `frame`, with the eased progress from one to the other.
+/// Outside the keyed frames both keys are the first or the last one.
+pub fn keyframe_segment<T>(
+    keyframes: &[AnimationKey<T>],
+    frame: f32,
+) -> BuildResult<(&AnimationKey<T>, &AnimationKey<T>, f32)> {
+    if let Some(key) = keyframes.iter().find(|key| !key.frame.is_finite()) {
+        return Err(BuildError::KeyframeTime(key.frame));
+    }
+    let mut keys = keyframes.iter().collect::<Vec<_>>();
+    keys.sort_by(|a, b| a.frame.total_cmp(&b.frame));
+    let next = keys.partition_point(|key| key.frame <= frame);
+    match (next, keys.len()) {
+        (_, 0) => Err(BuildError::NoKeyframes),
+        (0, _) => Ok((keys[0], keys[0], 0.)),
+        (next, len) if next == len => Ok((keys[len - 1], keys[len - 1], 0.)),
+        (next, _) => {
+            let (from, to) = (keys[next - 1], keys[next]);
+            let t = (frame - from.frame) / (to.frame - from.frame);
+            Ok((from, to, from.easing.ease(t)))
+        }
+    }
+}
+
+/// Value of the keyframed property at `frame`.
+pub fn keyframe_value<T: Lerp>(keyframes: &[AnimationKey<T>], frame: f32) -> BuildResult<T> {
+    let (from, to, t) = keyframe_segment(keyframes, frame)?;
+    Ok(from.value.lerp(to.value, t))
+}
+
+impl<T: Lerp> Animated<T> {
+    pub fn at(&self, frame: f32) -> BuildResult<T> {
+        match self {
+            Animated::Static(value) => Ok(*value),
+            Animated::Keyframed { keyframes } => keyframe_value(keyframes, frame),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key<T>(frame: f32, value: T, easing: Easing) -> AnimationKey<T> {
+        AnimationKey {
+            frame,
+            value,
+            easing,
+        }
+    }
+
+    #[test]
+    fn easing_curves() {
+        for easing in [
+            Easing::Linear,
+            Easing::EaseIn,
+            Easing::EaseOut,
+            Easing::EaseInOut,
+        ] {
+            assert_eq!(easing.ease(0.), 0.);
+            assert_eq!(easing.ease(1.), 1.);
+        }
+        assert!(Easing::EaseIn.ease(0.5) < 0.5);
+        assert!(Easing::EaseOut.ease(0.5) > 0.5);
+        assert_eq!(Easing::EaseInOut.ease(0.5), 0.5);
+        assert_eq!(Easing::Step.ease(0.9), 0.);
+    }
+
+    #[test]
+    fn keyframed_values() {
+        let animated = Animated::Keyframed {
+            keyframes: vec![
+                key(10., 4., Easing::Step),
+                key(0., 0., Easing::Linear),
+                key(20., 8., Easing::Linear),
+            ],
+        };
+        assert_eq!(animated.at(-5.), Ok(0.));
+        assert_eq!(animated.at(5.), Ok(2.));
+        assert_eq!(animated.at(15.), Ok(4.));
+        assert_eq!(animated.at(20.), Ok(8.));
+        assert_eq!(animated.at(25.), Ok(8.));
+        assert_eq!(Animated::Static(3.).at(25.), Ok(3.));
+
+        let empty: Animated<f32> = Animated::Keyframed { keyframes: vec![] };
+        assert_eq!(empty.at(0.), Err(BuildError::NoKeyframes));
+    }
+
+    #[test]
+    fn parse_keyframes() {
+        let yaml = r#"
+keyframes:
+  - frame: 1
+    value: [0, 0, 0]
+    easing: EaseInOut
+  - frame: 3
+    value: [2, 4, 0]
+"#;
+        let animated: Animated<Point> = parse_yaml(yaml).unwrap();
+        assert_eq!(animated.at(2.), Ok(Point(1., 2., 0.)));
+        let animated: Animated<Point> = parse_yaml("[1, 2, 3]").unwrap();
+        assert_eq!(animated, Animated::Static(Point(1., 2., 3.)));
+    }
+}
@@ -0,0 +1,677 @@
+extern crate image;
+extern crate rustracer_core;
+extern crate rustracer_parser;
+
+use std::mem::discriminant;
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+
+use image::ImageFormat;
+
+use rustracer_core::{
+    aov_filename, Aov, Camera, CancelToken, PartialRender, RenderProgress, World,
+};
+use rustracer_parser::*;
+
+const EXIT_USAGE: i32 = 1;
+const EXIT_PARSE: i32 = 2;
+const EXIT_BUILD: i32 = 3;
+const EXIT_IO: i32 = 4;
+
+const USAGE: &str = "
+Usage:  rustracer [options] <scene.yaml|scene.json>
+
+Options:
+    -o, --output <file>        Output image (default: output.png)
+    -f, --format <format>      Output format: png, jpeg, ppm, bmp, tga, tiff, exr, hdr...
+                               (default: guessed from the output extension)
+    -s, --size <W>x<H>         Override the camera size in pixels
+        --fov <degrees>        Override the camera field of view
+    -r, --max-reflects <n>     Override the maximum number of reflections
+    -a, --samples <n>          Override the number of samples per pixel
+    -i, --integrator <name>    Override the integrator: whitted, path for Monte
+                               Carlo path tracing, ao for ambient occlusion,
+                               or normals
+        --heatmap <file>       Also save an image of the number of samples per pixel
+        --aov <aov,...>        Also render AOVs: depth, normal, albedo, object_id,
+                               material_id, uv. Saved as layers of .exr outputs,
+                               or as <output>.<aov>.<ext> images otherwise
+    -j, --threads <n>          Number of render threads (default: number of CPUs)
+    -t, --tile-size <n>        Size in pixels of the square render tiles (default: 32)
+    -T, --time-limit <secs>    Stop rendering after the given time and save
+                               the pixels rendered so far
+    -c, --camera <name,...>    Render only the given cameras. Stereo rigs can be
+                               selected whole or by eye: <name>.left, <name>.right.
+                               With more than one camera, views are saved as
+                               <output>.<name>.<ext> (default: all cameras)
+        --frames <n>[-<m>]     Render frames n to m of the scene's animation, to
+                               files numbered in place of the #s of the output
+                               name, or as <output>.<frame>.<ext> otherwise.
+                               Frames already saved are skipped
+    -q, --quiet                Don't report progress
+    -h, --help                 Print this help
+
+Exit codes: 1 usage, 2 scene parse error, 3 scene build error, 4 I/O error.
+";
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    input: String,
+    output: String,
+    format: Option<ImageFormat>,
+    heatmap: Option<String>,
+    aovs: Vec<Aov>,
+    size: Option<(usize, usize)>,
+    field_of_view: Option<f32>,
+    max_reflects: Option<u8>,
+    samples: Option<usize>,
+    integrator: Option<Integrator>,
+    threads: Option<usize>,
+    tile_size: Option<usize>,
+    time_limit: Option<f32>,
+    cameras: Vec<String>,
+    frames: Option<(u32, u32)>,
+    quiet: bool,
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Render(Box<Options>),
+    Help,
+}
+
+fn parse_size(value: &str) -> Result<(usize, usize), String> {
+    let invalid = || format!("Invalid size '{}', expected <W>x<H>", value);
+    let (w, h) = value.split_once('x').ok_or_else(invalid)?;
+    let w = w.parse::<usize>().map_err(|_| invalid())?;
+    let h = h.parse::<usize>().map_err(|_| invalid())?;
+    if w == 0 || h == 0 {
+        return Err(invalid());
+    }
+    Ok((w, h))
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse::<T>()
+        .map_err(|_| format!("Invalid value '{}' for {}", value, flag))
+}
+
+fn parse_time_limit(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(secs) if secs.is_finite() && secs >= 0. => Ok(secs),
+        _ => Err(format!("Invalid time limit '{}'", value)),
+    }
+}
+
+fn parse_frames(value: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("Invalid frame range '{}', expected <n> or <n>-<m>", value);
+    let (first, last) = value.split_once('-').unwrap_or((value, value));
+    let first = first.parse::<u32>().map_err(|_| invalid())?;
+    let last = last.parse::<u32>().map_err(|_| invalid())?;
+    if last < first {
+        return Err(invalid());
+    }
+    Ok((first, last))
+}
+
+fn parse_format(value: &str) -> Result<ImageFormat, String> {
+    ImageFormat::from_extension(value).ok_or_else(|| format!("Unknown output format '{}'", value))
+}
+
+fn parse_aovs(value: &str) -> Result<Vec<Aov>, String> {
+    value
+        .split(',')
+        .map(|name| Aov::from_name(name.trim()).ok_or_else(|| format!("Unknown AOV '{}'", name)))
+        .collect()
+}
+
+fn parse_integrator(value: &str) -> Result<Integrator, String> {
+    match value {
+        "whitted" => Ok(Integrator::Whitted),
+        "path" => Ok(Integrator::PathTracing { max_bounces: 8 }),
+        "ao" => Ok(Integrator::AmbientOcclusion(AmbientOcclusion::default())),
+        "normals" => Ok(Integrator::Normals),
+        _ => Err(format!("Unknown integrator '{}'", value)),
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Command, String> {
+    let mut input = None;
+    let mut output = String::from("output.png");
+    let mut format = None;
+    let mut heatmap = None;
+    let mut aovs = vec![];
+    let mut size = None;
+    let mut field_of_view = None;
+    let mut max_reflects = None;
+    let mut samples = None;
+    let mut integrator = None;
+    let mut threads = None;
+    let mut tile_size = None;
+    let mut time_limit = None;
+    let mut cameras = vec![];
+    let mut frames = None;
+    let mut quiet = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let arg = arg.as_str();
+        if !arg.starts_with('-') || arg == "-" {
+            if input.replace(arg.to_string()).is_some() {
+                return Err(format!("Unexpected argument '{}'", arg));
+            }
+            continue;
+        }
+
+        match arg {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-q" | "--quiet" => {
+                quiet = true;
+                continue;
+            }
+            _ => {}
+        }
+
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {}", arg))?;
+        match arg {
+            "-o" | "--output" => output = value.clone(),
+            "-f" | "--format" => format = Some(parse_format(value)?),
+            "--heatmap" => heatmap = Some(value.clone()),
+            "--aov" => aovs = parse_aovs(value)?,
+            "-s" | "--size" => size = Some(parse_size(value)?),
+            "--fov" => field_of_view = Some(parse_number(arg, value)?),
+            "-r" | "--max-reflects" => max_reflects = Some(parse_number(arg, value)?),
+            "-a" | "--samples" => samples = Some(parse_number(arg, value)?),
+            "-i" | "--integrator" => integrator = Some(parse_integrator(value)?),
+            "-j" | "--threads" => threads = Some(parse_number(arg, value)?),
+            "-t" | "--tile-size" => tile_size = Some(parse_number(arg, value)?),
+            "-T" | "--time-limit" => time_limit = Some(parse_time_limit(value)?),
+            "-c" | "--camera" => cameras = value.split(',').map(|c| c.trim().to_string()).collect(),
+            "--frames" => frames = Some(parse_frames(value)?),
+            _ => return Err(format!("Unknown option '{}'", arg)),
+        }
+    }
+
+    let input = input.ok_or_else(|| String::from("Scene file required"))?;
+    Ok(Command::Render(Box::new(Options {
+        input,
+        output,
+        format,
+        heatmap,
+        aovs,
+        size,
+        field_of_view,
+        max_reflects,
+        samples,
+        integrator,
+        threads,
+        tile_size,
+        time_limit,
+        cameras,
+        frames,
+        quiet,
+    })))
+}
+
+fn report_progress(progress: &RenderProgress) {
+    let eta = progress
+        .eta()
+        .map_or(String::from("?"), |eta| format!("{}s", eta.as_secs()));
+    eprint!(
+        "\rRendering {:5.1}% ({}/{} tiles), {:.2} Mrays/s, ETA {}    ",
+        progress.fraction() * 100.,
+        progress.tiles_done,
+        progress.tiles_total,
+        progress.rays_per_second() / 1.0e6,
+        eta
+    );
+    if progress.tiles_done == progress.tiles_total {
+        eprintln!();
+    }
+}
+
+fn read_scene(file_name: &str) -> Scene {
+    let contents = std::fs::read_to_string(file_name).unwrap_or_else(|err| {
+        eprintln!("Couldn't read {}: {}", file_name, err);
+        process::exit(EXIT_IO);
+    });
+
+    let is_json = Path::new(file_name)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+    let scene = if is_json {
+        parse_json::<Scene>(&contents).map_err(|err| err.to_string())
+    } else {
+        parse_yaml::<Scene>(&contents).map_err(|err| err.to_string())
+    };
+
+    let mut scene = scene.unwrap_or_else(|err| {
+        eprintln!("Couldn't parse {}: {}", file_name, err);
+        process::exit(EXIT_PARSE);
+    });
+    if let Some(dir) = Path::new(file_name).parent() {
+        scene.resolve_paths(dir);
+    }
+    scene
+}
+
+/// Camera view to render, with the files it is saved to.
+struct View {
+    name: String,
+    camera: Camera,
+    integrator: Box<dyn rustracer_core::Integrator>,
+    output: String,
+    heatmap: Option<String>,
+}
+
+/// `name.ext` becomes `name.<view>.ext`.
+fn view_filename(output: &str, view: &str) -> String {
+    let path = Path::new(output);
+    match path.extension() {
+        Some(ext) => path
+            .with_extension(format!("{}.{}", view, ext.to_string_lossy()))
+            .to_string_lossy()
+            .into_owned(),
+        None => format!("{}.{}", output, view),
+    }
+}
+
+/// Output file of an animation frame: the last run of `#` in `output` is
+/// replaced by the zero padded frame number, which otherwise goes before the
+/// extension, padded to 4 digits.
+fn frame_filename(output: &str, frame: u32) -> String {
+    match output.rfind('#') {
+        Some(end) => {
+            let start = output[..end].trim_end_matches('#').len();
+            let width = end + 1 - start;
+            format!(
+                "{}{:0width$}{}",
+                &output[..start],
+                frame,
+                &output[end + 1..]
+            )
+        }
+        None => {
+            let path = Path::new(output);
+            let numbered = match path.extension() {
+                Some(ext) => path.with_extension(format!("{:04}.{}", frame, ext.to_string_lossy())),
+                None => path.with_extension(format!("{:04}", frame)),
+            };
+            numbered.to_string_lossy().into_owned()
+        }
+    }
+}
+
+/// Temporary file a frame is saved to before being renamed, so interrupted
+/// saves don't leave frames that look complete.
+fn partial_filename(output: &str) -> String {
+    let path = Path::new(output);
+    match path.extension() {
+        Some(ext) => path
+            .with_extension(format!("partial.{}", ext.to_string_lossy()))
+            .to_string_lossy()
+            .into_owned(),
+        None => format!("{}.partial", output),
+    }
+}
+
+/// Whether the camera `name` is picked by the `--camera` selection. Stereo
+/// eyes are also picked by the name of their rig.
+fn is_selected(selection: &[String], name: &str) -> bool {
+    selection.is_empty()
+        || selection.iter().any(|s| {
+            name == s
+                || name
+                    .strip_prefix(s.as_str())
+                    .is_some_and(|eye| eye.starts_with('.'))
+        })
+}
+
+fn build_failed(options: &Options, err: BuildError) -> ! {
+    eprintln!("Couldn't build {}: {}", options.input, err);
+    process::exit(EXIT_BUILD);
+}
+
+fn render(options: Options) {
+    let mut scene = read_scene(&options.input);
+    for camera in scene.cameras_mut() {
+        if let Some(size) = options.size {
+            camera.size = size;
+        }
+        if let Some(degrees) = options.field_of_view {
+            camera.field_of_view = Angle::Deg(degrees);
+        }
+        if let Some(max_reflects) = options.max_reflects {
+            camera.max_reflects = max_reflects;
+        }
+        if let Some(samples) = options.samples {
+            camera.samples = samples;
+        }
+        if let Some(integrator) = options.integrator {
+            // Keep the settings of the scene's integrator if it's the same one
+            if discriminant(&integrator) != discriminant(&camera.integrator) {
+                camera.integrator = integrator;
+            }
+        }
+    }
+    let cameras = build_cameras(&scene, 0.).unwrap_or_else(|err| build_failed(&options, err));
+    for selected in &options.cameras {
+        let selection = std::slice::from_ref(selected);
+        if !cameras
+            .iter()
+            .any(|(name, _, _)| is_selected(selection, name))
+        {
+            eprintln!("No camera named '{}' in {}", selected, options.input);
+            process::exit(EXIT_USAGE);
+        }
+    }
+
+    match options.frames {
+        Some((first, last)) => {
+            for frame in first..=last {
+                render_frame(&options, &scene, Some(frame));
+            }
+        }
+        None => render_frame(&options, &scene, None),
+    }
+}
+
+/// Renders the selected views of a frame, sharing one world. Views of an
+/// animation that are already saved are skipped.
+fn render_frame(options: &Options, scene: &Scene, frame: Option<u32>) {
+    let time = frame.unwrap_or(0) as f32;
+    let cameras = build_cameras(scene, time).unwrap_or_else(|err| build_failed(options, err));
+    let cameras = cameras
+        .into_iter()
+        .filter(|(name, _, _)| is_selected(&options.cameras, name))
+        .collect::<Vec<_>>();
+    let named = cameras.len() > 1;
+    let filename = |file: &str, name: &str| {
+        let file = if named {
+            view_filename(file, name)
+        } else {
+            file.to_string()
+        };
+        frame.map_or_else(|| file.clone(), |frame| frame_filename(&file, frame))
+    };
+    let mut views = cameras
+        .into_iter()
+        .map(|(name, camera, integrator)| View {
+            output: filename(&options.output, &name),
+            heatmap: options.heatmap.as_ref().map(|h| filename(h, &name)),
+            name,
+            camera,
+            integrator,
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(frame) = frame {
+        views.retain(|view| {
+            let saved = Path::new(&view.output).exists();
+            if saved && !options.quiet {
+                eprintln!("Frame {} already saved to {}, skipping", frame, view.output);
+            }
+            !saved
+        });
+        if views.is_empty() {
+            return;
+        }
+        if !options.quiet {
+            eprintln!("Frame {}", frame);
+        }
+    }
+
+    let world = build_world(scene, time).unwrap_or_else(|err| build_failed(options, err));
+    for view in views {
+        if named && !options.quiet {
+            eprintln!("Camera {}", view.name);
+        }
+        let partial = render_view(options, &world, view.camera, view.integrator.as_ref());
+        if frame.is_some() {
+            save_frame(options, &partial, &view.output, view.heatmap.as_deref());
+        } else {
+            save_render(options, &partial, &view.output, view.heatmap.as_deref());
+        }
+    }
+}
+
+fn render_view(
+    options: &Options,
+    world: &World,
+    mut camera: Camera,
+    integrator: &dyn rustracer_core::Integrator,
+) -> PartialRender {
+    if let Some(threads) = options.threads {
+        camera.set_threads(threads);
+    }
+    if let Some(tile_size) = options.tile_size {
+        camera.set_tile_size(tile_size);
+    }
+    camera.set_aovs(&options.aovs);
+
+    let budget = options.time_limit.map(Duration::from_secs_f32);
+    let token = CancelToken::new();
+    let partial = if options.quiet {
+        camera.render_partial(world, integrator, &token, budget, |_| {})
+    } else {
+        camera.render_partial(world, integrator, &token, budget, report_progress)
+    };
+    if !partial.is_complete() {
+        let mask = &partial.mask;
+        eprintln!(
+            "\nTime limit reached, {} of {} pixels rendered",
+            mask.count_done(),
+            mask.width * mask.height
+        );
+    }
+    partial
+}
+
+/// Saves to a temporary file first, renamed once complete. Frames cut short
+/// by the time limit keep the temporary name, so the next run renders them
+/// again.
+fn save_frame(options: &Options, partial: &PartialRender, output: &str, heatmap: Option<&str>) {
+    let temporary = partial_filename(output);
+    save_render(options, partial, &temporary, heatmap);
+    if !partial.is_complete() {
+        if !options.quiet {
+            eprintln!("Unfinished frame kept as {}", temporary);
+        }
+        return;
+    }
+    let mut renames = vec![(temporary.clone(), output.to_string())];
+    let format = options
+        .format
+        .or_else(|| ImageFormat::from_path(output).ok());
+    if format != Some(ImageFormat::OpenExr) {
+        renames.extend(partial.aovs.iter().map(|buffer| {
+            (
+                aov_filename(&temporary, buffer.aov),
+                aov_filename(output, buffer.aov),
+            )
+        }));
+    }
+    for (from, to) in renames {
+        if let Err(err) = std::fs::rename(&from, &to) {
+            eprintln!("Couldn't save {}: {}", to, err);
+            process::exit(EXIT_IO);
+        }
+    }
+}
+
+fn save_render(options: &Options, partial: &PartialRender, output: &str, heatmap: Option<&str>) {
+    if let Some(heatmap) = heatmap {
+        if let Err(err) = partial.sample_heatmap().save(heatmap) {
+            eprintln!("Couldn't save {}: {}", heatmap, err);
+            process::exit(EXIT_IO);
+        }
+    }
+    let canvas = &partial.canvas;
+    let saved = if partial.aovs.is_empty() {
+        match options.format {
+            Some(format) => canvas.save_with_format(output, format),
+            None => canvas.save(output),
+        }
+    } else {
+        options
+            .format
+            .map_or_else(|| ImageFormat::from_path(output), Ok)
+            .and_then(|format| canvas.save_with_aovs(output, format, &partial.aovs))
+    };
+    if let Err(err) = saved {
+        eprintln!("Couldn't save {}: {}", output, err);
+        process::exit(EXIT_IO);
+    }
+}
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match parse_args(&args) {
+        Ok(Command::Render(options)) => render(*options),
+        Ok(Command::Help) => println!("{}", USAGE),
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            process::exit(EXIT_USAGE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_defaults() {
+        let cmd = parse_args(&args(&["scene.yaml"])).unwrap();
+        assert_eq!(
+            cmd,
+            Command::Render(Box::new(Options {
+                input: String::from("scene.yaml"),
+                output: String::from("output.png"),
+                format: None,
+                heatmap: None,
+                aovs: vec![],
+                size: None,
+                field_of_view: None,
+                max_reflects: None,
+                samples: None,
+                integrator: None,
+                threads: None,
+                tile_size: None,
+                time_limit: None,
+                cameras: vec![],
+                frames: None,
+                quiet: false,
+            }))
+        );
+    }
+
+    #[test]
+    fn parse_overrides() {
+        let cmd = parse_args(&args(&[
+            "-s",
+            "320x240",
+            "--fov",
+            "45",
+            "-r",
+            "2",
+            "-a",
+            "4",
+            "-j",
+            "3",
+            "-o",
+            "out.ppm",
+            "-f",
+            "ppm",
+            "--heatmap",
+            "heat.png",
+            "-t",
+            "16",
+            "-T",
+            "1.5",
+            "-q",
+            "scene.json",
+            "--aov",
+            "depth,uv",
+            "--frames",
+            "3-10",
+            "-c",
+            "main, eyes.left",
+            "-i",
+            "path",
+        ]))
+        .unwrap();
+        assert_eq!(
+            cmd,
+            Command::Render(Box::new(Options {
+                input: String::from("scene.json"),
+                output: String::from("out.ppm"),
+                format: Some(ImageFormat::Pnm),
+                heatmap: Some(String::from("heat.png")),
+                aovs: vec![Aov::Depth, Aov::Uv],
+                size: Some((320, 240)),
+                field_of_view: Some(45.),
+                max_reflects: Some(2),
+                samples: Some(4),
+                integrator: Some(Integrator::PathTracing { max_bounces: 8 }),
+                threads: Some(3),
+                tile_size: Some(16),
+                time_limit: Some(1.5),
+                cameras: vec![String::from("main"), String::from("eyes.left")],
+                frames: Some((3, 10)),
+                quiet: true,
+            }))
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse_args(&args(&[])).is_err());
+        assert!(parse_args(&args(&["a.yaml", "b.yaml"])).is_err());
+        assert!(parse_args(&args(&["-s", "320", "a.yaml"])).is_err());
+        assert!(parse_args(&args(&["-s", "0x10", "a.yaml"])).is_err());
+        assert!(parse_args(&args(&["a.yaml", "-j"])).is_err());
+        assert!(parse_args(&args(&["--bogus", "1", "a.yaml"])).is_err());
+        assert!(parse_args(&args(&["-T", "-1", "a.yaml"])).is_err());
+        assert!(parse_args(&args(&["--aov", "depth,beauty", "a.yaml"])).is_err());
+        assert!(parse_args(&args(&["--frames", "5-2", "a.yaml"])).is_err());
+        assert!(parse_args(&args(&["--frames", "1-", "a.yaml"])).is_err());
+        assert!(parse_args(&args(&["-i", "radiosity", "a.yaml"])).is_err());
+        assert_eq!(parse_args(&args(&["-h"])), Ok(Command::Help));
+    }
+
+    #[test]
+    fn numbered_frames() {
+        assert_eq!(parse_frames("7"), Ok((7, 7)));
+        assert_eq!(frame_filename("out/frame_###.png", 7), "out/frame_007.png");
+        assert_eq!(frame_filename("f#_##.png", 1234), "f#_1234.png");
+        assert_eq!(frame_filename("out/render.png", 12), "out/render.0012.png");
+        assert_eq!(frame_filename("render", 3), "render.0003");
+        assert_eq!(
+            partial_filename("out/render.0012.png"),
+            "out/render.0012.partial.png"
+        );
+    }
+
+    #[test]
+    fn camera_selection() {
+        let selection = [String::from("main"), String::from("eyes")];
+        assert!(is_selected(&selection, "main"));
+        assert!(is_selected(&selection, "eyes.right"));
+        assert!(!is_selected(&selection, "eyes2"));
+        assert!(!is_selected(&selection, "side"));
+        assert!(is_selected(&[], "side"));
+        assert_eq!(
+            view_filename("out/render.png", "eyes.left"),
+            "out/render.eyes.left.png"
+        );
+    }
+}
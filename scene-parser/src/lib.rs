@@ -3,8 +3,10 @@ extern crate rustracer_core;
 extern crate serde_derive;
 extern crate yaml_merge_keys;
 
+pub use crate::animation::*;
 pub use crate::parser::*;
 pub use crate::types::*;
 
+mod animation;
 mod parser;
 mod types;
@@ -1,7 +1,91 @@
+use std::fmt;
+
 use rustracer_core as rc;
 
+use crate::animation::*;
 use crate::types::*;
 
+/// Errors found while turning a parsed `Scene` into a renderable world.
+#[derive(Debug, PartialEq)]
+pub enum BuildError {
+    EmptyPattern,
+    GradientValues(usize),
+    CameraSize(usize, usize),
+    AreaLightSteps(u8, u8),
+    WhitePoint(f32),
+    Aperture(f32),
+    FocalDistance(f32),
+    FocusConflict,
+    RoughnessConflict,
+    OrthographicSize(f32),
+    KeyframeTime(f32),
+    Shutter(f32, f32),
+    NoKeyframes,
+    NoCamera,
+    DuplicateCamera(String),
+    StereoRig(f32, f32),
+    OcclusionDistance(f32),
+    AbsorptionDensity(f32),
+    AbbeNumber(f32),
+    SpotAngles(f32, f32),
+    LightDirection(f32, f32, f32),
+    IesProfile(String, String),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use crate::BuildError::*;
+        match self {
+            EmptyPattern => write!(f, "pattern mappings need at least one value"),
+            GradientValues(n) => write!(f, "gradient mappings need 2 values, got {}", n),
+            CameraSize(h, v) => write!(f, "invalid camera size {}x{}", h, v),
+            AreaLightSteps(u, v) => write!(f, "invalid area light steps ({}, {})", u, v),
+            WhitePoint(w) => write!(f, "tone mapping white point must be positive, got {}", w),
+            Aperture(a) => write!(f, "camera aperture must not be negative, got {}", a),
+            FocalDistance(d) => write!(f, "focal distance must be positive, got {}", d),
+            FocusConflict => write!(f, "focal_distance and focus_on can't be used together"),
+            RoughnessConflict => write!(f, "microfacet materials take their roughness in brdf"),
+            OrthographicSize(s) => write!(f, "orthographic size must be positive, got {}", s),
+            KeyframeTime(t) => write!(f, "keyframe times must be finite, got {}", t),
+            Shutter(open, close) => write!(f, "invalid shutter interval ({}, {})", open, close),
+            NoKeyframes => write!(f, "keyframed values need at least one keyframe"),
+            NoCamera => write!(f, "the scene has no camera"),
+            DuplicateCamera(name) => write!(f, "more than one camera named '{}'", name),
+            StereoRig(interocular, convergence) => write!(
+                f,
+                "invalid stereo rig with interocular distance {} and convergence {}",
+                interocular, convergence
+            ),
+            OcclusionDistance(d) => {
+                write!(
+                    f,
+                    "ambient occlusion max_distance must be positive, got {}",
+                    d
+                )
+            }
+            AbsorptionDensity(d) => write!(f, "absorption density must not be negative, got {}", d),
+            AbbeNumber(v) => write!(f, "Abbe numbers must be positive, got {}", v),
+            SpotAngles(inner, outer) => write!(
+                f,
+                "spot light angles must satisfy 0 <= inner <= outer <= π, got {} and {}",
+                inner, outer
+            ),
+            LightDirection(x, y, z) => {
+                write!(
+                    f,
+                    "light direction must not be zero, got ({}, {}, {})",
+                    x, y, z
+                )
+            }
+            IesProfile(file, reason) => write!(f, "can't read IES profile {}: {}", file, reason),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+pub type BuildResult<T> = Result<T, BuildError>;
+
 pub fn parse_yaml<T>(yaml_str: &str) -> Result<T, serde_yaml::Error>
     where
             for<'de> T: serde::de::Deserialize<'de>,
@@ -11,77 +95,194 @@ pub fn parse_yaml<T>(yaml_str: &str) -> Result<T, serde_yaml::Error>
     serde_yaml::from_value(merged)
 }
 
-pub fn build_scene(scene: &Scene) -> (rc::World, rc::Camera) {
-    let Scene {
-        shapes,
-        lights,
-        camera,
-        ..
-    } = scene;
+pub fn parse_json<T>(json_str: &str) -> Result<T, serde_json::Error>
+where
+    for<'de> T: serde::de::Deserialize<'de>,
+{
+    serde_json::from_str(json_str)
+}
+
+/// A view of the scene: its name, camera and the integrator it renders with.
+pub type CameraView = (String, rc::Camera, Box<dyn rc::Integrator>);
+
+pub fn build_scene(scene: &Scene) -> BuildResult<(rc::World, rc::Camera, Box<dyn rc::Integrator>)> {
+    build_frame(scene, 0.)
+}
+
+/// Builds the scene as it is at `frame` of its animation, seen from its
+/// first camera.
+pub fn build_frame(
+    scene: &Scene,
+    frame: f32,
+) -> BuildResult<(rc::World, rc::Camera, Box<dyn rc::Integrator>)> {
+    let (_, camera, integrator) = build_cameras(scene, frame)?.swap_remove(0);
+    Ok((build_world(scene, frame)?, camera, integrator))
+}
+
+/// Shapes and lights of the scene at `frame`, which all its cameras share.
+pub fn build_world(scene: &Scene, frame: f32) -> BuildResult<rc::World> {
+    let Scene { shapes, lights, .. } = scene;
+
+    let rc_shapes: Vec<Box<dyn rc::Shape + Send>> = shapes
+        .iter()
+        .map(|s| build_shape(s, frame))
+        .collect::<BuildResult<_>>()?;
 
-    let rc_shapes: Vec<Box<dyn rc::Shape + Send>> = shapes.iter().map(build_shape).collect();
+    let rc_lights: Vec<rc::Light> = build_lights(lights, frame)?;
+    Ok(rc::World::new(rc_shapes, rc_lights))
+}
+
+/// Named views of the scene at `frame`: one per camera, or a `<name>.left`
+/// and `<name>.right` pair for stereo rigs. Unnamed cameras are called
+/// "camera".
+pub fn build_cameras(scene: &Scene, frame: f32) -> BuildResult<Vec<CameraView>> {
+    let mut names: Vec<&str> = vec![];
+    let mut views = vec![];
+    for camera in scene.cameras() {
+        let name = camera.name.as_deref().unwrap_or("camera");
+        if names.contains(&name) {
+            return Err(BuildError::DuplicateCamera(name.to_string()));
+        }
+        names.push(name);
 
-    let rc_lights: Vec<rc::Light> = build_lights(lights);
-    let rc_camera: rc::Camera = build_camera(camera);
-    (rc::World::new(rc_shapes, rc_lights), rc_camera)
+        let rc_camera = build_camera(camera, frame)?;
+        match camera.stereo {
+            None => views.push((name.to_string(), rc_camera, build_integrator(camera)?)),
+            Some(Stereo {
+                interocular,
+                convergence,
+            }) => {
+                let convergence = match convergence {
+                    Some(convergence) => convergence,
+                    None => {
+                        let view = build_point(&camera.to.at(frame)?)
+                            - build_point(&camera.from.at(frame)?);
+                        view.norm()
+                    }
+                };
+                let valid_interocular = interocular.is_finite() && interocular >= 0.;
+                let valid_convergence = convergence.is_finite() && convergence > 0.;
+                if !valid_interocular || !valid_convergence {
+                    return Err(BuildError::StereoRig(interocular, convergence));
+                }
+                let (left, right) = rc_camera.stereo_pair(interocular, convergence);
+                views.push((format!("{}.left", name), left, build_integrator(camera)?));
+                views.push((format!("{}.right", name), right, build_integrator(camera)?));
+            }
+        }
+    }
+    if views.is_empty() {
+        return Err(BuildError::NoCamera);
+    }
+    Ok(views)
 }
 
-fn build_shape(shape: &Shape) -> Box<dyn rc::Shape + Send> {
+fn build_shape(shape: &Shape, frame: f32) -> BuildResult<Box<dyn rc::Shape + Send>> {
     use crate::Shape::*;
-    match shape {
+    let mut rc_shape: Box<dyn rc::Shape + Send> = match shape {
         Plane {
-            base: BaseShape {
-                transform,
-                material,
-            },
+            base:
+                BaseShape {
+                    transform,
+                    material,
+                    ..
+                },
         } => Box::new(rc::Plane::new(
-            build_transforms(transform),
-            build_material(material),
+            build_animated_transforms(transform, frame)?,
+            build_material(material, frame)?,
         )),
         Cylinder {
             closed,
-            base: BaseShape {
-                transform,
-                material,
-            },
+            base:
+                BaseShape {
+                    transform,
+                    material,
+                    ..
+                },
         } => Box::new(rc::Cylinder::new(
-            build_transforms(transform),
-            build_material(material),
+            build_animated_transforms(transform, frame)?,
+            build_material(material, frame)?,
             *closed,
         )),
         Cube {
-            base: BaseShape {
-                transform,
-                material,
-            },
+            base:
+                BaseShape {
+                    transform,
+                    material,
+                    ..
+                },
         } => Box::new(rc::Cube::new(
-            build_transforms(transform),
-            build_material(material),
+            build_animated_transforms(transform, frame)?,
+            build_material(material, frame)?,
         )),
         Sphere {
-            base: BaseShape {
-                transform,
-                material,
-            },
+            base:
+                BaseShape {
+                    transform,
+                    material,
+                    ..
+                },
         } => Box::new(rc::Sphere::new(
-            build_transforms(transform),
-            build_material(material),
+            build_animated_transforms(transform, frame)?,
+            build_material(material, frame)?,
         )),
         Group {
             shapes,
-            base: BaseShape {
-                transform,
-                material,
-            },
+            base:
+                BaseShape {
+                    transform,
+                    material,
+                    ..
+                },
         } => {
-            let group = rc::Group::new(build_transforms(transform), build_material(material));
+            let group = rc::Group::new(
+                build_animated_transforms(transform, frame)?,
+                build_material(material, frame)?,
+            );
             let mut boxed_group = Box::new(group);
             for s in shapes {
-                let shape = build_shape(s);
+                let shape = build_shape(s, frame)?;
                 boxed_group.add_shape(shape);
             }
             boxed_group
         }
+    };
+    if let Some(motion) = build_motion(shape.base(), frame)? {
+        rc_shape.set_motion(motion);
+    }
+    Ok(rc_shape)
+}
+
+fn build_motion(base: &BaseShape, frame: f32) -> BuildResult<Option<rc::AnimatedTransform>> {
+    let transform = build_animated_transforms(&base.transform, frame)?;
+    let mut keyframes = Vec::with_capacity(base.motion.len());
+    for Keyframe {
+        time,
+        transform: keyframe_transform,
+    } in &base.motion
+    {
+        if !time.is_finite() {
+            return Err(BuildError::KeyframeTime(*time));
+        }
+        keyframes.push((*time, build_transforms(keyframe_transform) * transform));
+    }
+    Ok(rc::AnimatedTransform::new(&keyframes))
+}
+
+/// Keyframed transforms are decomposed and interpolated, like the motion of
+/// shapes.
+fn build_animated_transforms(
+    transforms: &Animated<Transforms>,
+    frame: f32,
+) -> BuildResult<rc::Transform> {
+    match transforms {
+        Animated::Static(transforms) => Ok(build_transforms(transforms)),
+        Animated::Keyframed { keyframes } => {
+            let (from, to, t) = keyframe_segment(keyframes, frame)?;
+            let from = rc::DecomposedTransform::new(&build_transforms(&from.value));
+            let to = rc::DecomposedTransform::new(&build_transforms(&to.value));
+            Ok(from.interpolate(&to, t).to_transform())
+        }
     }
 }
 
@@ -108,22 +309,68 @@ fn build_transform(transform: &Transform) -> rc::Transform {
     }
 }
 
-fn build_material(material: &Material) -> rc::Material {
-    rc::Material {
-        color: build_mapping(&material.color),
-        ambient: build_mapping(&material.ambient),
-        diffuse: build_mapping(&material.diffuse),
-        specular: build_mapping(&material.specular),
-        shininess: build_mapping(&material.shininess),
-        reflective: material.reflective.as_ref().map(build_mapping),
-        transparency: material.transparency.as_ref().map(build_mapping),
+fn build_material(material: &Material, frame: f32) -> BuildResult<rc::Material> {
+    let build_optional = |mapping: &Option<Mapping<f32>>| {
+        mapping
+            .as_ref()
+            .map(|m| build_mapping(m, frame))
+            .transpose()
+    };
+    let microfacet = matches!(material.brdf, Brdf::Microfacet { .. });
+    if microfacet && material.roughness != Mapping::Uniform(0.) {
+        return Err(BuildError::RoughnessConflict);
+    }
+    Ok(rc::Material {
+        color: build_mapping(&material.color, frame)?,
+        ambient: build_mapping(&material.ambient, frame)?,
+        diffuse: build_mapping(&material.diffuse, frame)?,
+        specular: build_mapping(&material.specular, frame)?,
+        shininess: build_mapping(&material.shininess, frame)?,
+        emission: material
+            .emission
+            .as_ref()
+            .map(|emission| build_mapping(emission, frame))
+            .transpose()?,
+        brdf: match &material.brdf {
+            Brdf::Phong => rc::Brdf::Phong,
+            Brdf::Microfacet {
+                metallic,
+                roughness,
+            } => rc::Brdf::Microfacet {
+                metallic: build_mapping(metallic, frame)?,
+                roughness: build_mapping(roughness, frame)?,
+            },
+        },
+        roughness: build_mapping(&material.roughness, frame)?,
+        reflective: build_optional(&material.reflective)?,
+        transparency: build_optional(&material.transparency)?,
         refractive_index: material.refractive_index,
+        dispersion: material.dispersion.map(build_dispersion).transpose()?,
+        absorption: material.absorption.map(build_absorption).transpose()?,
         attenuation: rc::Attenuation::None,
+        id: material.id,
+    })
+}
+
+fn build_dispersion(dispersion: Dispersion) -> BuildResult<rc::Dispersion> {
+    Ok(match dispersion {
+        Dispersion::Abbe(abbe) if abbe.is_nan() || abbe <= 0. => {
+            return Err(BuildError::AbbeNumber(abbe))
+        }
+        Dispersion::Abbe(abbe) => rc::Dispersion::Abbe(abbe),
+        Dispersion::Cauchy { a, b } => rc::Dispersion::Cauchy { a, b },
+    })
+}
+
+fn build_absorption(Absorption { color, density }: Absorption) -> BuildResult<rc::Absorption> {
+    if density.is_nan() || density < 0. {
+        return Err(BuildError::AbsorptionDensity(density));
     }
+    Ok(rc::Absorption::new(build_rgb(&color), density))
 }
 
 fn build_mapping<
-    F: Copy,
+    F: Lerp,
     T: Copy
     + core::ops::Sub<Output=T>
     + core::ops::Add<Output=T>
@@ -131,30 +378,39 @@ fn build_mapping<
     + From<F>,
 >(
     mapping: &Mapping<F>,
-) -> rc::Mapping<T> {
+    frame: f32,
+) -> BuildResult<rc::Mapping<T>> {
     use crate::Mapping::*;
     use crate::PatternMapping::*;
-    match mapping {
+    let mapping = match mapping {
         Uniform(value) => rc::Mapping::uniform((*value).into()),
+        Keyframed { keyframes } => rc::Mapping::uniform(keyframe_value(keyframes, frame)?.into()),
         Pattern(Stripes { values, transform }) => {
-            rc::Mapping::stripes(&map_vector(values), build_transforms(transform))
+            rc::Mapping::stripes(&map_vector(values)?, build_transforms(transform))
         }
         Pattern(Gradient { values, transform }) => {
-            let v = map_vector(values);
+            if values.len() != 2 {
+                return Err(BuildError::GradientValues(values.len()));
+            }
+            let v: Vec<T> = map_vector(values)?;
             let tuple = (v[0], v[1]);
             rc::Mapping::gradient(tuple, build_transforms(transform))
         }
         Pattern(Checkers { values, transform }) => {
-            rc::Mapping::checkers(&map_vector(values), build_transforms(transform))
+            rc::Mapping::checkers(&map_vector(values)?, build_transforms(transform))
         }
         Pattern(Rings { values, transform }) => {
-            rc::Mapping::rings(&map_vector(values), build_transforms(transform))
+            rc::Mapping::rings(&map_vector(values)?, build_transforms(transform))
         }
-    }
+    };
+    Ok(mapping)
 }
 
-fn map_vector<F: Copy, T: From<F>>(f: &[F]) -> Vec<T> {
-    f.iter().map(|&f| f.into()).collect()
+fn map_vector<F: Copy, T: From<F>>(f: &[F]) -> BuildResult<Vec<T>> {
+    if f.is_empty() {
+        return Err(BuildError::EmptyPattern);
+    }
+    Ok(f.iter().map(|&f| f.into()).collect())
 }
 
 impl From<Rgb> for rc::ColorRgbFloat {
@@ -163,18 +419,154 @@ impl From<Rgb> for rc::ColorRgbFloat {
     }
 }
 
-fn build_camera(camera: &Camera) -> rc::Camera {
+fn build_camera(camera: &Camera, frame: f32) -> BuildResult<rc::Camera> {
     let Camera {
         size: (h, w),
         field_of_view,
+        projection,
         from,
         to,
         up,
+        max_reflects: _,
+        samples,
+        sampling,
+        filter,
+        adaptive,
+        tone_map,
+        exposure,
+        aperture,
+        focal_distance,
+        focus_on,
+        blades,
+        shutter: (open, close),
+        name: _,
+        stereo: _,
+        integrator: _,
+        ambient_occlusion: _,
+        glossy_samples: _,
     } = camera;
-    let transform = rc::view_transform(build_point(from), build_point(to), build_vector(up));
+    if *h == 0 || *w == 0 {
+        return Err(BuildError::CameraSize(*h, *w));
+    }
+    let (from, to) = (from.at(frame)?, to.at(frame)?);
+    let transform = rc::view_transform(build_point(&from), build_point(&to), build_vector(up));
     let mut camera = rc::Camera::new(*h, *w, build_angle(*field_of_view));
     camera.set_transform(transform);
-    camera
+    camera.set_projection(build_projection(*projection)?);
+    camera.set_sampling(rc::PixelSampling {
+        adaptive: adaptive.map(
+            |Adaptive {
+                 max_samples,
+                 threshold,
+             }| rc::AdaptiveSampling {
+                max_samples,
+                threshold,
+            },
+        ),
+        ..rc::PixelSampling::new(build_sampling(*sampling), build_filter(*filter), *samples)
+    });
+    camera.set_tone_mapping(rc::ToneMapping::new(build_tone_map(*tone_map)?, *exposure));
+    if !open.is_finite() || !close.is_finite() || close < open {
+        return Err(BuildError::Shutter(*open, *close));
+    }
+    camera.set_shutter(*open, *close);
+    if *aperture < 0. {
+        return Err(BuildError::Aperture(*aperture));
+    }
+    if *aperture > 0. {
+        let from = build_point(&from);
+        let view = build_point(&to) - from;
+        let focal_distance = match (focal_distance, focus_on) {
+            (Some(_), Some(_)) => return Err(BuildError::FocusConflict),
+            (Some(distance), None) => *distance,
+            // Distance to the plane through the point, perpendicular to the view
+            (None, Some(focus)) => rc::dot(&(build_point(focus) - from), &view.normalize()),
+            (None, None) => view.norm(),
+        };
+        if focal_distance.is_nan() || focal_distance <= 0. {
+            return Err(BuildError::FocalDistance(focal_distance));
+        }
+        camera.set_lens(Some(rc::Lens {
+            blades: *blades,
+            ..rc::Lens::new(*aperture, focal_distance)
+        }));
+    }
+    Ok(camera)
+}
+
+fn build_integrator(camera: &Camera) -> BuildResult<Box<dyn rc::Integrator>> {
+    Ok(match camera.integrator {
+        Integrator::Whitted => Box::new(rc::Whitted {
+            ambient_occlusion: camera
+                .ambient_occlusion
+                .map(build_ambient_occlusion)
+                .transpose()?,
+            glossy_samples: camera.glossy_samples,
+            ..rc::Whitted::new(camera.max_reflects)
+        }),
+        Integrator::PathTracing { max_bounces } => Box::new(rc::PathTracer::new(max_bounces)),
+        Integrator::AmbientOcclusion(ao) => Box::new(build_ambient_occlusion(ao)?),
+        Integrator::Normals => Box::new(rc::DebugNormals),
+    })
+}
+
+fn build_ambient_occlusion(
+    AmbientOcclusion {
+        samples,
+        max_distance,
+    }: AmbientOcclusion,
+) -> BuildResult<rc::AmbientOcclusion> {
+    if max_distance.is_nan() || max_distance <= 0. {
+        return Err(BuildError::OcclusionDistance(max_distance));
+    }
+    Ok(rc::AmbientOcclusion::new(samples, max_distance))
+}
+
+fn build_sampling(sampling: Sampling) -> rc::SamplingStrategy {
+    match sampling {
+        Sampling::Regular => rc::SamplingStrategy::Regular,
+        Sampling::Stratified => rc::SamplingStrategy::Stratified,
+        Sampling::Halton => rc::SamplingStrategy::Halton,
+        Sampling::Sobol => rc::SamplingStrategy::Sobol,
+    }
+}
+
+fn build_projection(projection: Projection) -> BuildResult<rc::Projection> {
+    Ok(match projection {
+        Projection::Perspective => rc::Projection::Perspective,
+        Projection::Orthographic { size } => {
+            if size.is_nan() || size <= 0. {
+                return Err(BuildError::OrthographicSize(size));
+            }
+            rc::Projection::Orthographic { size }
+        }
+        Projection::Fisheye => rc::Projection::Fisheye,
+        Projection::Equirectangular => rc::Projection::Equirectangular,
+    })
+}
+
+fn build_tone_map(tone_map: ToneMap) -> BuildResult<rc::ToneMapOperator> {
+    Ok(match tone_map {
+        ToneMap::Linear => rc::ToneMapOperator::Linear,
+        ToneMap::Reinhard => rc::ToneMapOperator::Reinhard,
+        ToneMap::ExtendedReinhard { white_point } => {
+            if white_point.is_nan() || white_point <= 0. {
+                return Err(BuildError::WhitePoint(white_point));
+            }
+            rc::ToneMapOperator::ExtendedReinhard { white_point }
+        }
+        ToneMap::Aces => rc::ToneMapOperator::Aces,
+        ToneMap::Hable => rc::ToneMapOperator::Hable,
+    })
+}
+
+fn build_filter(filter: Filter) -> rc::Filter {
+    match filter {
+        Filter::Box => rc::Filter::Box,
+        Filter::Tent => rc::Filter::Tent,
+        Filter::Gaussian => rc::Filter::Gaussian,
+        Filter::Mitchell => rc::Filter::Mitchell,
+    }
 }
 
 fn build_point(p: &Point) -> rc::Point {
@@ -192,18 +584,21 @@ fn build_vector(v: &Vector) -> rc::Vector {
     rc::vector(x, y, z)
 }
 
-fn build_lights(lights: &[Light]) -> Vec<rc::Light> {
-    lights.iter().map(build_light).collect()
+fn build_lights(lights: &[Light], frame: f32) -> BuildResult<Vec<rc::Light>> {
+    lights
+        .iter()
+        .map(|light| build_light(light, frame))
+        .collect()
 }
 
-fn build_light(light: &Light) -> rc::Light {
+fn build_light(light: &Light, frame: f32) -> BuildResult<rc::Light> {
     use crate::Light::*;
-    match light {
+    let light = match light {
         PointLight {
             position,
             intensity,
         } => rc::Light::Point(rc::PointLight::new(
-            build_point(position),
+            build_point(&position.at(frame)?),
             build_rgb(intensity),
         )),
         AreaLight {
@@ -212,21 +607,69 @@ fn build_light(light: &Light) -> rc::Light {
             uv,
             steps,
             jitter,
-        } => rc::Light::Area(rc::AreaLight::new(
-            build_point(position),
-            build_rgb(intensity),
-            (build_vector(&uv.0), build_vector(&uv.1)),
-            (steps.0, steps.1),
-            *jitter,
-        )),
+        } => {
+            if steps.0 == 0 || steps.1 == 0 {
+                return Err(BuildError::AreaLightSteps(steps.0, steps.1));
+            }
+            rc::Light::Area(rc::AreaLight::new(
+                build_point(&position.at(frame)?),
+                build_rgb(intensity),
+                (build_vector(&uv.0), build_vector(&uv.1)),
+                (steps.0, steps.1),
+                *jitter,
+            ))
+        }
         DirectionalLight {
             direction,
             intensity,
         } => rc::Light::Directional(rc::DirectionalLight::new(
-            rc::normalize(&build_vector(direction)),
+            build_direction(direction)?,
             build_rgb(intensity),
         )),
+        SpotLight {
+            position,
+            direction,
+            intensity,
+            inner_angle,
+            outer_angle,
+            ies,
+        } => {
+            let (default_inner, default_outer) = match ies {
+                Some(_) => (Angle::Pi, Angle::Pi),
+                None => (Angle::FPi6, Angle::FPi4),
+            };
+            let inner = build_angle(inner_angle.unwrap_or(default_inner));
+            let outer = build_angle(outer_angle.unwrap_or(default_outer));
+            if !(0. <= inner && inner <= outer && outer <= std::f32::consts::PI) {
+                return Err(BuildError::SpotAngles(inner, outer));
+            }
+            let mut spot_light = rc::SpotLight::new(
+                build_point(&position.at(frame)?),
+                build_direction(direction)?,
+                build_rgb(intensity),
+                inner,
+                outer,
+            );
+            spot_light.profile = ies.as_deref().map(build_ies_profile).transpose()?;
+            rc::Light::Spot(spot_light)
+        }
+    };
+    Ok(light)
+}
+
+fn build_direction(direction: &Vector) -> BuildResult<rc::UnitVector> {
+    let Vector(x, y, z) = *direction;
+    let v = build_vector(direction);
+    if !v.norm().is_normal() {
+        return Err(BuildError::LightDirection(x, y, z));
     }
+    Ok(rc::normalize(&v))
+}
+
+fn build_ies_profile(file: &str) -> BuildResult<rc::IesProfile> {
+    let error = |reason: String| BuildError::IesProfile(file.to_string(), reason);
+    let text = std::fs::read_to_string(file).map_err(|e| error(e.to_string()))?;
+    rc::IesProfile::parse(&text).map_err(|e| error(e.to_string()))
 }
 
 fn build_angle(angle: Angle) -> f32 {
@@ -243,3 +686,358 @@ fn build_angle(angle: Angle) -> f32 {
         Deg(deg) => PI * deg / 180.,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_invalid_gradient() {
+        let yaml = r#"
+---
+shapes:
+  - Sphere:
+      material:
+        color:
+          Gradient:
+            values: [ [ 1, 0, 0 ] ]
+lights: []
+camera: {}
+"#;
+        let scene: Scene = parse_yaml(yaml).unwrap();
+        assert_eq!(
+            build_scene(&scene).err(),
+            Some(BuildError::GradientValues(1))
+        );
+    }
+
+    #[test]
+    fn build_absorbing_material() {
+        let mut material: Material = parse_yaml("absorption: { color: [1, 0.5, 0.5] }").unwrap();
+        let glass = build_material(&material, 0.).unwrap();
+        let absorption = glass.absorption.unwrap();
+        assert_eq!(absorption.transmittance(2.), rc::color(1., 0.25, 0.25));
+        material.absorption = Some(Absorption {
+            density: -1.,
+            ..Absorption::default()
+        });
+        let error = build_material(&material, 0.).err();
+        assert_eq!(error, Some(BuildError::AbsorptionDensity(-1.)));
+    }
+
+    #[test]
+    fn build_dispersive_material() {
+        let yaml = "{ refractive_index: 1.5, dispersion: { Abbe: 50 } }";
+        let mut material: Material = parse_yaml(yaml).unwrap();
+        let glass = build_material(&material, 0.).unwrap();
+        let index = |channel: usize| glass.refractive_index_at(Some(rc::CHANNELS[channel].0));
+        // The index spreads by (n - 1) / V between the C and F lines
+        assert!((index(2) - index(0) - 0.01).abs() < 1e-4);
+        assert!((index(1) - 1.5).abs() < 1e-6);
+        material.dispersion = Some(Dispersion::Abbe(0.));
+        assert_eq!(
+            build_material(&material, 0.).err(),
+            Some(BuildError::AbbeNumber(0.))
+        );
+    }
+
+    #[test]
+    fn build_microfacet_material() {
+        let yaml = "{ brdf: { Microfacet: { roughness: 0.4 } }, reflective: 1 }";
+        let mut material: Material = parse_yaml(yaml).unwrap();
+        let metal = build_material(&material, 0.).unwrap();
+        let shape = rc::Sphere::new(rc::Transform::identity(), metal.clone());
+        let ray = rc::Ray::new(rc::point(0., 0., -5.), rc::vector(0., 0., 1.));
+        let intersection = rc::Intersection::new(4., &shape);
+        let hit = intersection.prepare_hit(&ray);
+        // The glossy reflections follow the roughness of the brdf
+        assert_eq!(metal.get_hit_material(&hit).roughness, 0.4);
+        material.roughness = Mapping::Uniform(0.2);
+        assert_eq!(
+            build_material(&material, 0.).err(),
+            Some(BuildError::RoughnessConflict)
+        );
+    }
+
+    #[test]
+    fn build_emissive_shapes() {
+        let yaml = r#"
+---
+shapes:
+  - Sphere:
+      material:
+        emission: [2, 2, 1]
+  - Plane: {}
+lights:
+  - PointLight:
+      position: [0, 5, 0]
+      intensity: [1, 1, 1]
+camera: {}
+"#;
+        let scene: Scene = parse_yaml(yaml).unwrap();
+        let world = build_world(&scene, 0.).unwrap();
+        assert_eq!(world.lights.len(), 2);
+        let emissive = &world.lights[1];
+        assert!(matches!(
+            emissive,
+            rc::Light::Emissive(rc::EmissiveLight { shape: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn build_spot_light() {
+        use std::f32::consts::*;
+        let spot = |extra: &str| {
+            let yaml = format!(
+                "SpotLight: {{ position: [0, 5, 0], direction: [0, -2, 0]{} }}",
+                extra
+            );
+            build_light(&parse_yaml(&yaml).unwrap(), 0.)
+        };
+        let Ok(rc::Light::Spot(light)) = spot("") else {
+            panic!("not a spot light");
+        };
+        assert_eq!(light.direction, rc::unit_vector(0., -1., 0.));
+        assert!((light.outer_angle - FRAC_PI_4).abs() < 1e-6);
+        assert!(light.profile.is_none());
+        let error = spot(", inner_angle: FPi2").err();
+        assert_eq!(error, Some(BuildError::SpotAngles(FRAC_PI_2, FRAC_PI_4)));
+        let yaml = "SpotLight: { position: [0, 5, 0], direction: [0, 0, 0] }";
+        let error = build_light(&parse_yaml(yaml).unwrap(), 0.).err();
+        assert_eq!(error, Some(BuildError::LightDirection(0., 0., 0.)));
+
+        // Without angles, a profile isn't limited by a cone
+        let file = std::env::temp_dir().join("rustracer-spot-light.ies");
+        let profile = "TILT=NONE\n1 1000 1 2 1 1 1 0 0 0\n1 1 50\n0 90\n0\n100 20";
+        std::fs::write(&file, profile).unwrap();
+        let ies = format!(", ies: {}", file.display());
+        let Ok(rc::Light::Spot(light)) = spot(&ies) else {
+            panic!("not a spot light");
+        };
+        assert_eq!(light.outer_angle, PI);
+        assert_eq!(light.profile.unwrap().vertical_angles, vec![0., 90.]);
+        std::fs::remove_file(&file).unwrap();
+        assert!(matches!(spot(&ies), Err(BuildError::IesProfile(..))));
+    }
+
+    #[test]
+    fn build_invalid_camera_size() {
+        let json = r#"{ "shapes": [], "lights": [], "camera": { "size": [0, 10] } }"#;
+        let scene: Scene = parse_json(json).unwrap();
+        assert_eq!(
+            build_scene(&scene).err(),
+            Some(BuildError::CameraSize(0, 10))
+        );
+    }
+
+    #[test]
+    fn build_focus() {
+        let yaml = r#"
+---
+from: [0, 0, -5]
+to: [0, 0, 0]
+aperture: 0.1
+focus_on: [3, 1, 2]
+"#;
+        let mut camera: Camera = parse_yaml(yaml).unwrap();
+        let rc_camera = build_camera(&camera, 0.).unwrap();
+        // Rays from anywhere on the lens meet on the plane z = 2
+        let a = rc_camera.ray_through_lens(0.5, 0.5, (0., 0.));
+        let b = rc_camera.ray_through_lens(0.5, 0.5, (1., 1.));
+        let at_focus = |r: &rc::Ray| r.position((2. - r.origin.z) / r.direction.z);
+        assert!((at_focus(&a) - at_focus(&b)).norm() < 1e-4);
+
+        camera.focal_distance = Some(2.);
+        assert_eq!(
+            build_camera(&camera, 0.).err(),
+            Some(BuildError::FocusConflict)
+        );
+        camera.focus_on = Some(Point(0., 0., -10.));
+        camera.focal_distance = None;
+        assert_eq!(
+            build_camera(&camera, 0.).err(),
+            Some(BuildError::FocalDistance(-5.))
+        );
+    }
+
+    #[test]
+    fn build_invalid_white_point() {
+        let yaml = r#"
+---
+shapes: []
+lights: []
+camera:
+  tone_map:
+    ExtendedReinhard:
+      white_point: 0
+"#;
+        let scene: Scene = parse_yaml(yaml).unwrap();
+        assert_eq!(build_scene(&scene).err(), Some(BuildError::WhitePoint(0.)));
+        let white_point = f32::NAN;
+        let error = build_tone_map(ToneMap::ExtendedReinhard { white_point }).err();
+        assert!(matches!(error, Some(BuildError::WhitePoint(w)) if w.is_nan()));
+    }
+
+    #[test]
+    fn build_motion_blur() {
+        let yaml = r#"
+---
+Sphere:
+  transform:
+    - Scaling: [2, 2, 2]
+  motion:
+    - time: 1
+      transform:
+        - Translation: [4, 0, 0]
+    - time: 0
+      transform:
+        - Identity
+"#;
+        let shape = build_shape(&parse_yaml(yaml).unwrap(), 0.).unwrap();
+        let origin = rc::point(1., 0., 0.);
+        let transform_at = |time| shape.get_transform_inverse_at(time).inverse();
+        assert!((transform_at(0.) * origin - rc::point(2., 0., 0.)).norm() < 1e-4);
+        assert!((transform_at(0.5) * origin - rc::point(4., 0., 0.)).norm() < 1e-4);
+        let (min, max) = shape.get_parent_bounds();
+        assert!((min - rc::point(-2., -2., -2.)).norm() < 1e-4);
+        assert!((max - rc::point(6., 2., 2.)).norm() < 1e-4);
+
+        let camera: Camera = parse_yaml("shutter: [1, 0.5]").unwrap();
+        assert_eq!(
+            build_camera(&camera, 0.).err(),
+            Some(BuildError::Shutter(1., 0.5))
+        );
+    }
+
+    #[test]
+    fn build_animated_frames() {
+        let yaml = r#"
+---
+shapes:
+  - Sphere:
+      transform:
+        keyframes:
+          - frame: 0
+            value:
+              - Translation: [0, 0, 0]
+          - frame: 10
+            value:
+              - RotationY: FPi2
+              - Translation: [4, 0, 0]
+      material:
+        ambient:
+          keyframes:
+            - { frame: 0, value: 0, easing: Step }
+            - { frame: 10, value: 1 }
+lights:
+  - PointLight:
+      position:
+        keyframes:
+          - { frame: 0, value: [0, 10, 0], easing: EaseIn }
+          - { frame: 10, value: [10, 10, 0] }
+camera:
+  from:
+    keyframes:
+      - { frame: 0, value: [0, 0, -5] }
+      - { frame: 10, value: [0, 0, -10] }
+"#;
+        let scene: Scene = parse_yaml(yaml).unwrap();
+        let (world, camera, _) = build_frame(&scene, 5.).unwrap();
+        let sphere = &world.bounded_shapes[0].shape;
+        let centre = sphere.get_transform() * rc::point(0., 0., 0.);
+        assert!((centre - rc::point(2., 0., 0.)).norm() < 1e-4);
+        // Halfway through a quarter turn, +x is rotated by 45°
+        let x_axis = sphere.get_transform() * rc::vector(1., 0., 0.);
+        let diagonal = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((x_axis - rc::vector(diagonal, 0., -diagonal)).norm() < 1e-4);
+        let ambient = sphere
+            .get_material()
+            .ambient
+            .map_at_object(&rc::point(0., 0., 0.));
+        assert_eq!(ambient, 0.);
+        let rc::Light::Point(light) = &world.lights[0] else {
+            panic!("expected a point light");
+        };
+        assert!((light.position - rc::point(1.25, 10., 0.)).norm() < 1e-4);
+        let origin = camera.ray_for_pixel(0, 0).origin;
+        assert!((origin - rc::point(0., 0., -7.5)).norm() < 1e-4);
+
+        let (world, _, _) = build_frame(&scene, 12.).unwrap();
+        let ambient = &world.bounded_shapes[0].shape.get_material().ambient;
+        assert_eq!(ambient.map_at_object(&rc::point(0., 0., 0.)), 1.);
+    }
+
+    #[test]
+    fn build_named_cameras() {
+        let yaml = r#"
+---
+shapes: []
+lights: []
+camera:
+  from: [0, 0, -5]
+  to: [0, 0, 0]
+cameras:
+  - name: eyes
+    from: [0, 0, -4]
+    to: [0, 0, 0]
+    stereo:
+      interocular: 0.2
+"#;
+        let mut scene: Scene = parse_yaml(yaml).unwrap();
+        let views = build_cameras(&scene, 0.).unwrap();
+        let names = views
+            .iter()
+            .map(|(name, _, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["camera", "eyes.left", "eyes.right"]);
+        // Rendered with the default Whitted tracer
+        let world = rc::World::default();
+        let ray = rc::Ray::new(rc::point(0., 0., -5.), rc::vector(0., 0., 1.));
+        let mut rng = rand::thread_rng();
+        let expected = rc::Integrator::radiance(&rc::Whitted::default(), &world, &ray, &mut rng);
+        assert_eq!(views[0].2.radiance(&world, &ray, &mut rng), expected);
+        // The eyes converge on `to`
+        let (_, left, _) = &views[1];
+        let r = left.ray_for_position(800., 600.);
+        assert!((r.origin - rc::point(-0.1, 0., -4.)).norm() < 1e-4);
+        assert!((r.position(4. / r.direction.z) - rc::point(0., 0., 0.)).norm() < 1e-4);
+
+        scene.cameras[0].name = None;
+        let error = build_cameras(&scene, 0.).err();
+        assert_eq!(
+            error,
+            Some(BuildError::DuplicateCamera(String::from("camera")))
+        );
+        scene.camera = None;
+        scene.cameras.clear();
+        assert_eq!(build_cameras(&scene, 0.).err(), Some(BuildError::NoCamera));
+    }
+
+    #[test]
+    fn build_ambient_occlusion() {
+        let yaml = r#"
+---
+integrator:
+  AmbientOcclusion: { samples: 4 }
+ambient_occlusion:
+  max_distance: 0
+"#;
+        let mut camera: Camera = parse_yaml(yaml).unwrap();
+        let ao = build_integrator(&camera).unwrap();
+        // A floor under a ceiling, further away than the default max_distance
+        // of 1 and close enough to hide part of the sky of 4 samples
+        let floor_under = |height: f32| {
+            let floor = rc::Plane::new(rc::Transform::identity(), rc::Material::default());
+            let ceiling = rc::Plane::new(rc::translation(0., height, 0.), rc::Material::default());
+            rc::World::new(vec![Box::new(floor), Box::new(ceiling)], vec![])
+        };
+        let ray = rc::Ray::new(rc::point(0., 0.1, 0.), rc::vector(0., -1., 0.));
+        let mut rng = rand::thread_rng();
+        assert_eq!(ao.radiance(&floor_under(1.5), &ray, &mut rng), rc::WHITE);
+        let visibility = ao.radiance(&floor_under(0.5), &ray, &mut rng).r;
+        assert_eq!((visibility * 4.).fract(), 0.);
+        camera.integrator = Integrator::Whitted;
+        let error = build_integrator(&camera).err();
+        assert_eq!(error, Some(BuildError::OcclusionDistance(0.)));
+    }
+}
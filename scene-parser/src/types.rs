@@ -30,9 +30,41 @@ pub enum Transforms {
 #[serde(untagged)]
 pub enum Mapping<T> {
     Uniform(T),
+    Keyframed { keyframes: Vec<AnimationKey<T>> },
     Pattern(PatternMapping<T>),
 }
 
+// Animation
+
+/// Value that is either fixed or keyframed over the frames of an animation.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(untagged)]
+pub enum Animated<T> {
+    Static(T),
+    Keyframed { keyframes: Vec<AnimationKey<T>> },
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct AnimationKey<T> {
+    pub frame: f32,
+    pub value: T,
+    /// Easing of the way to the next key.
+    #[serde(default)]
+    pub easing: Easing,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+pub enum Easing {
+    #[default]
+    Linear,
+    /// Holds the value until the next key.
+    Step,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub enum PatternMapping<T> {
     Stripes {
@@ -65,9 +97,63 @@ pub struct Material {
     pub diffuse: Mapping<f32>,
     pub specular: Mapping<f32>,
     pub shininess: Mapping<f32>,
+    /// Light given off by the surface, which makes the shape a light source.
+    pub emission: Option<Mapping<Rgb>>,
+    pub brdf: Brdf,
+    /// Spread of the reflected and refracted rays, from 0 (mirror) to 1. Only
+    /// for the Phong brdf, the microfacet one has its own roughness.
+    pub roughness: Mapping<f32>,
     pub reflective: Option<Mapping<f32>>,
     pub transparency: Option<Mapping<f32>>,
     pub refractive_index: f32,
+    pub dispersion: Option<Dispersion>,
+    pub absorption: Option<Absorption>,
+    pub id: u32,
+}
+
+/// Variation of the refractive index with the wavelength.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+#[serde(deny_unknown_fields)]
+pub enum Dispersion {
+    /// Abbe number, around the `refractive_index` of the material.
+    Abbe(f32),
+    /// Cauchy's equation `a + b / λ²` with λ in micrometres, replacing the
+    /// `refractive_index` of the material.
+    Cauchy { a: f32, b: f32 },
+}
+
+/// Tint of the light travelling inside transparent materials: white light
+/// comes out as `color` after a distance of 1 at a `density` of 1.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Absorption {
+    pub color: Rgb,
+    pub density: f32,
+}
+
+impl Default for Absorption {
+    fn default() -> Absorption {
+        Absorption {
+            color: Rgb::default(),
+            density: 1.,
+        }
+    }
+}
+
+/// Reflectance model of a material.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub enum Brdf {
+    /// From the `diffuse`, `specular` and `shininess` of the material.
+    Phong,
+    /// Physically based GGX microfacets, with the material `color` as base
+    /// colour.
+    Microfacet {
+        #[serde(default = "default_metallic")]
+        metallic: Mapping<f32>,
+        #[serde(default = "default_roughness")]
+        roughness: Mapping<f32>,
+    },
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
@@ -114,9 +200,30 @@ pub enum Shape {
 #[serde(default, deny_unknown_fields)]
 pub struct BaseShape {
     pub material: Material,
+    pub transform: Animated<Transforms>,
+    /// Keyframes of a moving shape, applied on top of `transform`.
+    pub motion: Vec<Keyframe>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Keyframe {
+    pub time: f32,
     pub transform: Transforms,
 }
 
+impl Shape {
+    pub fn base(&self) -> &BaseShape {
+        match self {
+            Shape::Plane { base }
+            | Shape::Cylinder { base, .. }
+            | Shape::Sphere { base }
+            | Shape::Cube { base }
+            | Shape::Group { base, .. } => base,
+        }
+    }
+}
+
 // Camera & Lights
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
@@ -124,22 +231,133 @@ pub struct BaseShape {
 pub struct Camera {
     pub size: (usize, usize),
     pub field_of_view: Angle,
-    pub from: Point,
-    pub to: Point,
+    pub projection: Projection,
+    pub from: Animated<Point>,
+    pub to: Animated<Point>,
     pub up: Vector,
+    /// Depth of the reflections and refractions traced by `Whitted`.
+    pub max_reflects: u8,
+    pub samples: usize,
+    pub sampling: Sampling,
+    pub filter: Filter,
+    pub adaptive: Option<Adaptive>,
+    pub tone_map: ToneMap,
+    pub exposure: f32,
+    pub aperture: f32,
+    pub focal_distance: Option<f32>,
+    pub focus_on: Option<Point>,
+    pub blades: u8,
+    pub shutter: (f32, f32),
+    /// Name of the view, used to select it and in output file names.
+    pub name: Option<String>,
+    pub stereo: Option<Stereo>,
+    pub integrator: Integrator,
+    /// Darkens the ambient term of the `Whitted` integrator in occluded areas.
+    pub ambient_occlusion: Option<AmbientOcclusion>,
+    /// Rays traced by `Whitted` for rough reflections and refractions seen
+    /// from the camera.
+    pub glossy_samples: usize,
+}
+
+/// Pair of cameras, one per eye, in place of a single one.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Stereo {
+    pub interocular: f32,
+    /// Distance to the plane without parallax. Defaults to the distance from
+    /// `from` to `to`.
+    #[serde(default)]
+    pub convergence: Option<f32>,
+}
+
+/// How the light arriving along the camera rays is computed.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+#[serde(deny_unknown_fields)]
+pub enum Integrator {
+    /// Mirror reflections and refractions only, with ambient light.
+    Whitted,
+    /// Monte Carlo path tracing of the indirect light.
+    PathTracing {
+        #[serde(default = "default_max_bounces")]
+        max_bounces: u8,
+    },
+    /// Ambient occlusion of the first hit, in grey levels.
+    AmbientOcclusion(AmbientOcclusion),
+    /// Shading normals of the first hit, for debugging.
+    Normals,
+}
+
+/// Hemisphere rays cast per hit, and the distance up to which shapes occlude.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AmbientOcclusion {
+    pub samples: usize,
+    pub max_distance: f32,
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> AmbientOcclusion {
+        AmbientOcclusion {
+            samples: 16,
+            max_distance: 1.,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+#[serde(deny_unknown_fields)]
+pub enum Projection {
+    Perspective,
+    Orthographic { size: f32 },
+    Fisheye,
+    Equirectangular,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Adaptive {
+    pub max_samples: usize,
+    #[serde(default = "default_adaptive_threshold")]
+    pub threshold: f32,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum Sampling {
+    Regular,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum Filter {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+#[serde(deny_unknown_fields)]
+pub enum ToneMap {
+    Linear,
+    Reinhard,
+    ExtendedReinhard { white_point: f32 },
+    Aces,
+    Hable,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 #[serde(deny_unknown_fields)]
 pub enum Light {
     PointLight {
-        position: Point,
+        position: Animated<Point>,
         #[serde(default)]
         intensity: Rgb,
     },
 
     AreaLight {
-        position: Point,
+        position: Animated<Point>,
         #[serde(default)]
         intensity: Rgb,
         uv: (Vector, Vector),
@@ -151,6 +369,21 @@ pub enum Light {
         #[serde(default)]
         intensity: Rgb,
     },
+    /// Cone of light with full intensity within `inner_angle` of `direction`,
+    /// fading out up to `outer_angle`. The angles default to 30° and 45°, or
+    /// to no cone at all with an `ies` photometric profile file.
+    SpotLight {
+        position: Animated<Point>,
+        direction: Vector,
+        #[serde(default)]
+        intensity: Rgb,
+        #[serde(default)]
+        inner_angle: Option<Angle>,
+        #[serde(default)]
+        outer_angle: Option<Angle>,
+        #[serde(default)]
+        ies: Option<String>,
+    },
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
@@ -160,7 +393,34 @@ pub struct Scene {
     pub fragments: Vec<Fragment>,
     pub shapes: Vec<Shape>,
     pub lights: Vec<Light>,
-    pub camera: Camera,
+    #[serde(default)]
+    pub camera: Option<Camera>,
+    #[serde(default)]
+    pub cameras: Vec<Camera>,
+}
+
+impl Scene {
+    /// `camera` followed by the list of `cameras`.
+    pub fn cameras(&self) -> impl Iterator<Item = &Camera> {
+        self.camera.iter().chain(self.cameras.iter())
+    }
+
+    pub fn cameras_mut(&mut self) -> impl Iterator<Item = &mut Camera> {
+        self.camera.iter_mut().chain(self.cameras.iter_mut())
+    }
+
+    /// Makes the relative paths of the files the scene refers to, like `ies`
+    /// profiles, relative to `dir` instead of the working directory.
+    pub fn resolve_paths(&mut self, dir: &std::path::Path) {
+        for light in &mut self.lights {
+            if let Light::SpotLight {
+                ies: Some(file), ..
+            } = light
+            {
+                *file = dir.join(&*file).to_string_lossy().into_owned();
+            }
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
@@ -187,13 +447,25 @@ impl Default for Material {
             diffuse: Mapping::Uniform(0.6),
             specular: Mapping::Uniform(0.1),
             shininess: Mapping::Uniform(7.0),
+            emission: None,
+            brdf: Brdf::Phong,
+            roughness: Mapping::Uniform(0.),
             reflective: None,
             transparency: None,
             refractive_index: 1.0,
+            dispersion: None,
+            absorption: None,
+            id: 0,
         }
     }
 }
 
+impl<T: Default> Default for Animated<T> {
+    fn default() -> Animated<T> {
+        Animated::Static(T::default())
+    }
+}
+
 impl Default for Transforms {
     fn default() -> Transforms {
         Transforms::SingleTransform(Transform::Identity)
@@ -205,13 +477,47 @@ impl Default for Camera {
         Camera {
             size: (1600, 1200),
             field_of_view: Angle::FPi4,
-            from: Point(1., 1., 0.),
-            to: Point(0., 0., 0.),
+            projection: Projection::Perspective,
+            from: Animated::Static(Point(1., 1., 0.)),
+            to: Animated::Static(Point(0., 0., 0.)),
             up: Vector(0., 1., 0.),
+            max_reflects: 5,
+            samples: 1,
+            sampling: Sampling::Regular,
+            filter: Filter::Box,
+            adaptive: None,
+            tone_map: ToneMap::Linear,
+            exposure: 0.,
+            aperture: 0.,
+            focal_distance: None,
+            focus_on: None,
+            blades: 0,
+            shutter: (0., 0.),
+            name: None,
+            stereo: None,
+            integrator: Integrator::Whitted,
+            ambient_occlusion: None,
+            glossy_samples: 8,
         }
     }
 }
 
+fn default_adaptive_threshold() -> f32 {
+    0.01
+}
+
+fn default_max_bounces() -> u8 {
+    8
+}
+
+fn default_metallic() -> Mapping<f32> {
+    Mapping::Uniform(0.)
+}
+
+fn default_roughness() -> Mapping<f32> {
+    Mapping::Uniform(0.5)
+}
+
 // ===============
 // TESTS
 // ===============
@@ -259,7 +565,8 @@ mod tests {
                         }),
                         ..Material::default()
                     },
-                    transform: Transforms::default(),
+                    transform: Animated::default(),
+                    motion: vec![],
                 }
             }
         );
@@ -292,14 +599,122 @@ Sphere:
                         }),
                         ..Material::default()
                     },
-                    transform: Transforms::ChainedTransform(vec![Transforms::SingleTransform(
-                        Transform::Scaling(1., 2., 3.)
-                    )]),
+                    transform: Animated::Static(Transforms::ChainedTransform(vec![
+                        Transforms::SingleTransform(Transform::Scaling(1., 2., 3.))
+                    ])),
+                    motion: vec![],
                 }
             }
         );
     }
 
+    #[test]
+    fn test_microfacet_material() {
+        let yaml = r#"
+---
+color: [1, 0.8, 0.3]
+brdf:
+  Microfacet:
+    metallic: 1
+    roughness:
+      Stripes:
+        values: [0.2, 0.6]
+"#;
+        let res: Material = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(
+            res.brdf,
+            Brdf::Microfacet {
+                metallic: Mapping::Uniform(1.),
+                roughness: Mapping::Pattern(PatternMapping::Stripes {
+                    values: vec![0.2, 0.6],
+                    transform: Transforms::default(),
+                }),
+            }
+        );
+        let res: Material = serde_yaml::from_str("brdf: { Microfacet: {} }").unwrap();
+        let defaults = Brdf::Microfacet {
+            metallic: Mapping::Uniform(0.),
+            roughness: Mapping::Uniform(0.5),
+        };
+        assert_eq!(res.brdf, defaults);
+        assert_eq!(res.roughness, Mapping::Uniform(0.));
+    }
+
+    #[test]
+    fn test_frosted_glass() {
+        let yaml = "{ transparency: 1, refractive_index: 1.5, roughness: 0.3 }";
+        let res: Material = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(res.roughness, Mapping::Uniform(0.3));
+        assert_eq!(res.transparency, Some(Mapping::Uniform(1.)));
+    }
+
+    #[test]
+    fn test_dispersion() {
+        let res: Material = serde_yaml::from_str("dispersion: { Abbe: 30 }").unwrap();
+        assert_eq!(res.dispersion, Some(Dispersion::Abbe(30.)));
+        let yaml = "dispersion: { Cauchy: { a: 1.5, b: 0.004 } }";
+        let res: Material = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(
+            res.dispersion,
+            Some(Dispersion::Cauchy { a: 1.5, b: 0.004 })
+        );
+    }
+
+    #[test]
+    fn test_spot_light() {
+        let yaml = r#"
+SpotLight:
+  position: [0, 5, 0]
+  direction: [0, -1, 0]
+  outer_angle: { Deg: 40 }
+  ies: downlight.ies
+"#;
+        let res: Light = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(
+            res,
+            Light::SpotLight {
+                position: Animated::Static(Point(0., 5., 0.)),
+                direction: Vector(0., -1., 0.),
+                intensity: Rgb::default(),
+                inner_angle: None,
+                outer_angle: Some(Angle::Deg(40.)),
+                ies: Some("downlight.ies".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_resolve_paths() {
+        let yaml = r#"
+shapes: []
+lights:
+  - SpotLight: { position: [0, 5, 0], direction: [0, -1, 0], ies: downlight.ies }
+  - SpotLight: { position: [0, 5, 0], direction: [0, -1, 0], ies: /lamps/wall.ies }
+"#;
+        let mut scene: Scene = serde_yaml::from_str(yaml).unwrap();
+        scene.resolve_paths(std::path::Path::new("scenes/room"));
+        let files = scene
+            .lights
+            .iter()
+            .map(|light| match light {
+                Light::SpotLight { ies, .. } => ies.clone().unwrap(),
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(files, ["scenes/room/downlight.ies", "/lamps/wall.ies"]);
+    }
+
+    #[test]
+    fn test_coloured_glass() {
+        let yaml = "{ transparency: 1, absorption: { color: [0.2, 0.8, 0.4] } }";
+        let res: Material = serde_yaml::from_str(yaml).unwrap();
+        let absorption = Absorption {
+            color: Rgb(0.2, 0.8, 0.4),
+            density: 1.,
+        };
+        assert_eq!(res.absorption, Some(absorption));
+    }
+
     #[test]
     fn test_simple_camera() {
         let yaml = r#"
@@ -313,10 +728,110 @@ from: [10, 10, 10]
             Camera {
                 size: (1600, 1200),
                 field_of_view: Angle::FPi4,
-                from: Point(10.0, 10.0, 10.0),
-                to: Point(0.0, 0.0, 0.0),
+                projection: Projection::Perspective,
+                from: Animated::Static(Point(10.0, 10.0, 10.0)),
+                to: Animated::Static(Point(0.0, 0.0, 0.0)),
                 up: Vector(0.0, 1.0, 0.0),
+                max_reflects: 5,
+                samples: 1,
+                sampling: Sampling::Regular,
+                filter: Filter::Box,
+                adaptive: None,
+                tone_map: ToneMap::Linear,
+                exposure: 0.,
+                aperture: 0.,
+                focal_distance: None,
+                focus_on: None,
+                blades: 0,
+                shutter: (0., 0.),
+                name: None,
+                stereo: None,
+                integrator: Integrator::Whitted,
+                ambient_occlusion: None,
+                glossy_samples: 8,
             }
         );
     }
+
+    #[test]
+    fn test_path_tracing_camera() {
+        let res: Camera = serde_yaml::from_str("integrator: { PathTracing: {} }").unwrap();
+        assert_eq!(res.integrator, Integrator::PathTracing { max_bounces: 8 });
+        let res: Camera =
+            serde_yaml::from_str("integrator: { PathTracing: { max_bounces: 3 } }").unwrap();
+        assert_eq!(res.integrator, Integrator::PathTracing { max_bounces: 3 });
+        let res: Camera = serde_yaml::from_str("integrator: Normals").unwrap();
+        assert_eq!(res.integrator, Integrator::Normals);
+    }
+
+    #[test]
+    fn test_ambient_occlusion_camera() {
+        let yaml = r#"
+---
+integrator:
+  AmbientOcclusion: { max_distance: 2 }
+ambient_occlusion:
+  samples: 8
+"#;
+        let res: Camera = serde_yaml::from_str(yaml).unwrap();
+        let ao = AmbientOcclusion {
+            samples: 16,
+            max_distance: 2.,
+        };
+        assert_eq!(res.integrator, Integrator::AmbientOcclusion(ao));
+        assert_eq!(res.ambient_occlusion.map(|ao| ao.samples), Some(8));
+    }
+
+    #[test]
+    fn test_antialiased_camera() {
+        let yaml = r#"
+---
+samples: 16
+sampling: Halton
+filter: Mitchell
+adaptive:
+    max_samples: 64
+"#;
+        let res: Camera = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(res.samples, 16);
+        assert_eq!(res.sampling, Sampling::Halton);
+        assert_eq!(res.filter, Filter::Mitchell);
+        assert_eq!(
+            res.adaptive,
+            Some(Adaptive {
+                max_samples: 64,
+                threshold: 0.01,
+            })
+        );
+    }
+
+    #[test]
+    fn test_tone_mapped_camera() {
+        let yaml = r#"
+---
+tone_map:
+    ExtendedReinhard:
+        white_point: 4
+exposure: -1.5
+"#;
+        let res: Camera = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(res.tone_map, ToneMap::ExtendedReinhard { white_point: 4. });
+        assert_eq!(res.exposure, -1.5);
+        let res: Camera = serde_yaml::from_str("tone_map: Aces").unwrap();
+        assert_eq!(res.tone_map, ToneMap::Aces);
+    }
+
+    #[test]
+    fn test_camera_projection() {
+        let res: Camera = serde_yaml::from_str("projection: Equirectangular").unwrap();
+        assert_eq!(res.projection, Projection::Equirectangular);
+        let yaml = r#"
+---
+projection:
+    Orthographic:
+        size: 12
+"#;
+        let res: Camera = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(res.projection, Projection::Orthographic { size: 12. });
+    }
 }